@@ -37,6 +37,14 @@ struct database_writer {
     int poolfd;
 };
 
+/* Sits between libarchive and the output so the compressed database can
+ * be measured and hashed as it's written, without reading it back */
+struct database_output {
+    int fd;
+    size_t size;
+    SHA256_CTX ctx;
+};
+
 /* The name, version and type fields all share the same memory */
 struct entry_info {
     char *name;
@@ -65,9 +73,18 @@ static char *sha256_fd(int fd)
 
 static char *sha256_file(int dirfd, const char *filename)
 {
+    struct timespec start;
+    struct stat st;
+
+    timer_start(&start);
     _cleanup_close_ int fd = openat(dirfd, filename, O_RDONLY);
     check_posix(fd, "failed to open %s for sha256 checksum", filename);
-    return sha256_fd(fd);
+    check_posix(fstat(fd, &st), "failed to stat %s", filename);
+
+    char *sha256sum = sha256_fd(fd);
+    debug("hashed %s (%jd bytes) in %.3fs\n", filename, (intmax_t)st.st_size,
+          timer_elapsed(&start));
+    return sha256sum;
 }
 
 static int parse_database_pathname(const char *entryname, struct entry_info *entry)
@@ -126,18 +143,24 @@ static struct pkg *get_package(struct database_reader *db, struct entry_info *en
     return pkg;
 }
 
-static bool is_database_metadata(const char *filename)
+/* Find out if this is a member we parse, and if so where the fields in
+   it we don't recognize should be kept */
+static bool is_database_metadata(const char *filename, enum pkg_entry *unknown)
 {
-    static const char *metadata_names[] = {
-        "desc",
-        "depends",
-        "files",
-        NULL
+    static const struct {
+        const char *name;
+        enum pkg_entry unknown;
+    } metadata[] = {
+        { "desc",    PKG_UNKNOWN_DESC },
+        { "depends", PKG_UNKNOWN_DEPENDS },
+        { "files",   PKG_UNKNOWN_FILES },
     };
 
-    for (const char **n = metadata_names; *n; ++n) {
-        if (streq(filename, *n))
+    for (size_t i = 0; i < sizeof(metadata) / sizeof(metadata[0]); ++i) {
+        if (streq(filename, metadata[i].name)) {
+            *unknown = metadata[i].unknown;
             return true;
+        }
     }
 
     return false;
@@ -148,6 +171,7 @@ static int parse_database_entry(struct database_reader *db, struct archive_entry
 {
     const char *pathname = archive_entry_pathname(entry);
     struct entry_info entry_info;
+    enum pkg_entry unknown;
     int ret = 0;
 
     if (parse_database_pathname(pathname, &entry_info) < 0) {
@@ -155,7 +179,7 @@ static int parse_database_entry(struct database_reader *db, struct archive_entry
         goto cleanup;
     }
 
-    if (entry_info.type && is_database_metadata(entry_info.type)) {
+    if (entry_info.type && is_database_metadata(entry_info.type, &unknown)) {
         bool allocate = !streq(entry_info.type, "files");
 
         struct pkg *pkg = get_package(db, &entry_info, pkgcache, allocate);
@@ -164,9 +188,19 @@ static int parse_database_entry(struct database_reader *db, struct archive_entry
             goto cleanup;
         }
 
-        if (pkg && read_desc(db->archive, pkg) < 0) {
+        if (pkg && read_desc(db->archive, pkg, unknown) < 0) {
             errx(EXIT_FAILURE, "failed to parse %s for %s", entry_info.type, pathname);
         }
+
+        /* A files database made by repo-add repeats desc, so the same
+           directory turning up again isn't a duplicate */
+        if (pkg && streq(entry_info.type, "desc")) {
+            char *folder = strndup(pathname, strchrnul(pathname, '/') - pathname);
+            if (alpm_list_find_str(pkg->entries, folder))
+                free(folder);
+            else
+                pkg->entries = alpm_list_add(pkg->entries, folder);
+        }
     }
 
 cleanup:
@@ -241,76 +275,12 @@ static void commit_entry(struct database_writer *db, const char *name,
     buffer_clear(&db->buf);
 }
 
-static void write_list(struct buffer *buf, const char *header, const alpm_list_t *lst)
-{
-    if (lst == NULL)
-        return;
-
-    buffer_printf(buf, "%%%s%%\n", header);
-    for (; lst; lst = lst->next)
-        buffer_printf(buf, "%s\n", (const char *)lst->data);
-    buffer_putc(buf, '\n');
-}
-
-static void write_string(struct buffer *buf, const char *header, const char *str)
-{
-    if (str == NULL)
-        return;
-
-    buffer_printf(buf, "%%%s%%\n%s\n\n", header, str);
-}
-
-static void write_size(struct buffer *buf, const char *header, size_t val)
-{
-    buffer_printf(buf, "%%%s%%\n%zd\n\n", header, val);
-}
-
-static void write_time(struct buffer *buf, const char *header, time_t val)
-{
-    buffer_printf(buf, "%%%s%%\n%ld\n\n", header, val);
-}
-
-#define write_entry(buf, header, val) _Generic((val), \
-    alpm_list_t *: write_list, \
-    char *: write_string, \
-    size_t: write_size, \
-    time_t: write_time)(buf, header, val)
-
 static void compile_desc_entry(struct database_writer *db, struct pkg *pkg)
 {
-    write_entry(&db->buf, "FILENAME",  pkg->filename);
-    write_entry(&db->buf, "NAME",      pkg->name);
-    write_entry(&db->buf, "BASE",      pkg->base);
-    write_entry(&db->buf, "VERSION",   pkg->version);
-    write_entry(&db->buf, "DESC",      pkg->desc);
-    write_entry(&db->buf, "GROUPS",    pkg->groups);
-    write_entry(&db->buf, "CSIZE",     pkg->size);
-    write_entry(&db->buf, "ISIZE",     pkg->isize);
-
-    if (pkg->base64sig) {
-        write_entry(&db->buf, "PGPSIG", pkg->base64sig);
-    } else {
-        if (!pkg->sha256sum)
-            pkg->sha256sum = sha256_file(db->poolfd, pkg->filename);
-        write_entry(&db->buf, "SHA256SUM", pkg->sha256sum);
-    }
+    if (!pkg->base64sig && !pkg->sha256sum)
+        pkg->sha256sum = sha256_file(db->poolfd, pkg->filename);
 
-    write_entry(&db->buf, "URL",       pkg->url);
-    write_entry(&db->buf, "LICENSE",   pkg->licenses);
-    write_entry(&db->buf, "ARCH",      pkg->arch);
-    write_entry(&db->buf, "BUILDDATE", pkg->builddate);
-    write_entry(&db->buf, "PACKAGER",  pkg->packager);
-    write_entry(&db->buf, "REPLACES",  pkg->replaces);
-}
-
-static void compile_depends_entry(struct database_writer *db, struct pkg *pkg)
-{
-    write_entry(&db->buf, "DEPENDS",      pkg->depends);
-    write_entry(&db->buf, "CONFLICTS",    pkg->conflicts);
-    write_entry(&db->buf, "PROVIDES",     pkg->provides);
-    write_entry(&db->buf, "OPTDEPENDS",   pkg->optdepends);
-    write_entry(&db->buf, "MAKEDEPENDS",  pkg->makedepends);
-    write_entry(&db->buf, "CHECKDEPENDS", pkg->checkdepends);
+    write_desc(&db->buf, pkg);
 }
 
 static void compile_files_entry(struct database_writer *db, struct pkg *pkg)
@@ -320,10 +290,15 @@ static void compile_files_entry(struct database_writer *db, struct pkg *pkg)
         if (pkgfd < 0 && errno != ENOENT)
             err(EXIT_FAILURE, "failed to open %s", pkg->filename);
 
-        load_package_files(pkg, pkgfd);
+        /* Only packages that were just added or updated get here, the
+           rest already have their file list from the last run. Nothing
+           has been moved into place yet, so bailing out is safe. */
+        if (load_package_files(pkg, pkgfd) < 0 && errno == EPERM)
+            errx(EXIT_FAILURE, "%s contains a path outside the package root, refusing to index it",
+                 pkg->filename);
     }
 
-    write_entry(&db->buf, "FILES", pkg->files);
+    write_files(&db->buf, pkg);
 }
 
 static void compile_database_entry(struct database_writer *db, struct pkg *pkg)
@@ -339,7 +314,7 @@ static void compile_database_entry(struct database_writer *db, struct pkg *pkg)
         commit_entry(db, "desc", folder);
     }
     if (db->contents & DB_DEPENDS) {
-        compile_depends_entry(db, pkg);
+        write_depends(&db->buf, pkg);
         commit_entry(db, "depends", folder);
     }
     if (db->contents & DB_FILES) {
@@ -347,20 +322,34 @@ static void compile_database_entry(struct database_writer *db, struct pkg *pkg)
         commit_entry(db, "files", folder);
     }
     if (db->contents & DB_DELTAS) {
-        write_entry(&db->buf, "DELTAS", pkg->deltas);
+        write_deltas(&db->buf, pkg);
         commit_entry(db, "deltas", folder);
     }
 }
 
-static int compile_database(struct repo *repo, const char *repo_name,
-                            enum contents what)
+static ssize_t database_output_write(struct archive *archive, void *data,
+                                     const void *buf, size_t len)
 {
-    int ret = 0;
-    _cleanup_close_ int dbfd = openat(repo->rootfd, repo_name,
-                                      O_CREAT | O_WRONLY | O_TRUNC, 0644);
-    if (dbfd < 0)
+    struct database_output *out = data;
+
+    ssize_t nbytes_w = write(out->fd, buf, len);
+    if (nbytes_w < 0) {
+        archive_set_error(archive, errno, "failed to write database");
         return -1;
+    }
+
+    SHA256_Update(&out->ctx, buf, nbytes_w);
+    out->size += nbytes_w;
+    return nbytes_w;
+}
 
+/* Stream a database out to any file descriptor. Nothing here requires the
+ * output to be seekable, so pipes and sockets work just as well as files. */
+int compile_database(struct repo *repo, int dbfd, enum contents what,
+                     struct write_summary *summary)
+{
+    int ret = 0;
+    struct database_output out = { .fd = dbfd };
     struct database_writer db = {
         .archive = archive_write_new(),
         .entry = archive_entry_new(),
@@ -372,7 +361,14 @@ static int compile_database(struct repo *repo, const char *repo_name,
     archive_write_add_filter(db.archive, config.compression);
     archive_write_set_format_pax_restricted(db.archive);
 
-    if (archive_write_open_fd(db.archive, dbfd) < 0) {
+    if (config.compression_level >= 0) {
+        char level[4];
+        snprintf(level, sizeof(level), "%d", config.compression_level);
+        archive_write_set_filter_option(db.archive, NULL, "compression-level", level);
+    }
+
+    SHA256_Init(&out.ctx);
+    if (archive_write_open(db.archive, &out, NULL, database_output_write, NULL) < 0) {
         ret = -1;
         goto cleanup;
     }
@@ -381,6 +377,11 @@ static int compile_database(struct repo *repo, const char *repo_name,
     archive_write_header(db.archive, db.entry);
     archive_entry_clear(db.entry);
 
+    /* Write the entries in name order, the only key a database has, so
+     * the same set of packages always lays out the same way no matter
+     * what order the pool was scanned in */
+    pkgcache_sort(repo->cache);
+
     /* The files database can get very, very large. Lets allocate a
      * 2MiB buffer so we have plenty of room and avoid reallocation. */
     buffer_reserve(&db.buf, 0x200000);
@@ -391,23 +392,133 @@ static int compile_database(struct repo *repo, const char *repo_name,
         compile_database_entry(&db, pkg);
     }
 
-    archive_write_close(db.archive);
+    if (archive_write_close(db.archive) < 0)
+        ret = -1;
     buffer_release(&db.buf);
 
+    if (summary) {
+        unsigned char output[SHA256_DIGEST_LENGTH];
+        SHA256_Final(output, &out.ctx);
+
+        summary->size = out.size;
+        summary->sha256sum = hex_representation(output, sizeof(output));
+    }
+
 cleanup:
     archive_entry_free(db.entry);
     archive_write_free(db.archive);
     return ret;
 }
 
-int write_database(struct repo *repo, const char *repo_name, enum contents what)
+/* Databases are built under a temporary name and only moved into place
+ * by publish_database, once they've been checked and signed, so if
+ * anything goes wrong along the way the old database and its signature
+ * are still there, untouched */
+char *database_tmpname(const char *repo_name)
 {
+    return joinstring(".", repo_name, ".tmp", NULL);
+}
+
+int write_database(struct repo *repo, const char *repo_name, enum contents what,
+                   struct write_summary *summary)
+{
+    struct timespec start;
+    struct write_summary written = {0};
+
     trace("writing %s...\n", repo_name);
-    check_posix(compile_database(repo, repo_name, what),
+    timer_start(&start);
+
+    _cleanup_free_ char *tmpname = database_tmpname(repo_name);
+
+    _cleanup_close_ int dbfd = openat(repo->rootfd, tmpname,
+                                      O_CREAT | O_WRONLY | O_TRUNC, 0644);
+    check_posix(dbfd, "failed to open %s database", repo_name);
+    check_posix(compile_database(repo, dbfd, what, &written),
                 "failed to write %s database", repo_name);
+    debug("wrote %s (%zu bytes, sha256 %s) in %.3fs\n", repo_name, written.size,
+          written.sha256sum, timer_elapsed(&start));
 
-    if (config.sign)
-        gpgme_sign(repo->rootfd, repo_name, NULL);
+    if (summary)
+        *summary = written;
+    else
+        free(written.sha256sum);
 
     return 0;
 }
+
+void publish_database(struct repo *repo, const char *repo_name)
+{
+    _cleanup_free_ char *tmpname = database_tmpname(repo_name);
+
+    if (config.sign) {
+        struct timespec start;
+
+        timer_start(&start);
+        gpgme_sign(repo->rootfd, tmpname, config.fingerprint);
+        debug("signed %s in %.3fs\n", repo_name, timer_elapsed(&start));
+
+        _cleanup_free_ char *tmpsig = joinstring(tmpname, ".sig", NULL);
+        _cleanup_free_ char *sig = joinstring(repo_name, ".sig", NULL);
+        check_posix(renameat(repo->rootfd, tmpsig, repo->rootfd, sig),
+                    "failed to rename %s", tmpsig);
+    }
+
+    check_posix(renameat(repo->rootfd, tmpname, repo->rootfd, repo_name),
+                "failed to rename %s", tmpname);
+}
+
+static void write_checksum(FILE *fp, int dirfd, const char *filename)
+{
+    _cleanup_free_ char *sha256sum = sha256_file(dirfd, filename);
+    fprintf(fp, "%s  %s\n", sha256sum, filename);
+}
+
+/* Write a sha256sum(1) compatible list of everything a mirror has to
+ * carry: every package in the repository and the databases themselves.
+ * The databases were hashed as they were written. */
+void write_checksums(struct repo *repo, const char *filename,
+                     const struct write_summary *db, const struct write_summary *files)
+{
+    trace("writing %s...\n", filename);
+
+    int fd = openat(repo->rootfd, filename, O_CREAT | O_WRONLY | O_TRUNC, 0644);
+    check_posix(fd, "failed to open %s", filename);
+    _cleanup_fclose_ FILE *fp = fdopen(fd, "w");
+    if (!fp) {
+        const int saved_errno = errno;
+        close(fd);
+        errno = saved_errno;
+        err(EXIT_FAILURE, "failed to open %s", filename);
+    }
+
+    alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        struct pkg *pkg = node->data;
+
+        if (!pkg->sha256sum)
+            pkg->sha256sum = sha256_file(repo->poolfd, pkg->filename);
+        fprintf(fp, "%s  %s\n", pkg->sha256sum, pkg->filename);
+    }
+
+    const struct {
+        const char *name;
+        const struct write_summary *summary;
+    } databases[] = {
+        { repo->dbname, db },
+        { repo->filesname, files },
+    };
+
+    for (size_t i = 0; i < sizeof(databases) / sizeof(databases[0]); ++i) {
+        if (!databases[i].name)
+            continue;
+
+        fprintf(fp, "%s  %s\n", databases[i].summary->sha256sum, databases[i].name);
+        if (config.sign) {
+            _cleanup_free_ char *signame = joinstring(databases[i].name, ".sig", NULL);
+            write_checksum(fp, repo->rootfd, signame);
+        }
+    }
+
+    if (fflush(fp) == EOF)
+        err(EXIT_FAILURE, "failed to write %s", filename);
+}
@@ -11,5 +11,18 @@ enum contents {
     DB_DELTAS  = 1 << 4
 };
 
+/* What we learned about a database while streaming it out */
+struct write_summary {
+    size_t size;
+    char *sha256sum;
+};
+
 int load_database(int fd, struct pkgcache **pkgcache);
-int write_database(struct repo *repo, const char *repo_name, enum contents what);
+int compile_database(struct repo *repo, int fd, enum contents what,
+                     struct write_summary *summary);
+char *database_tmpname(const char *repo_name);
+int write_database(struct repo *repo, const char *repo_name, enum contents what,
+                   struct write_summary *summary);
+void publish_database(struct repo *repo, const char *repo_name);
+void write_checksums(struct repo *repo, const char *filename,
+                     const struct write_summary *db, const struct write_summary *files);
@@ -1,15 +1,21 @@
 #pragma once
 
 #include <stddef.h>
+#include <stdbool.h>
 #include <sys/types.h>
 #include <limits.h>
 #include "package.h"
 
 struct archive;
+struct buffer;
 
 struct desc_parser {
     int cs;
     enum pkg_entry entry;
+    /* Which of the PKG_UNKNOWN_* lists fields we don't recognize go
+       in, depending on the member being parsed. Defaults to desc. */
+    enum pkg_entry unknown;
+    bool cleared_unknown;
     size_t pos;
     char store[LINE_MAX];
 };
@@ -17,4 +23,15 @@ struct desc_parser {
 void desc_parser_init(struct desc_parser *parser);
 ssize_t desc_parser_feed(struct desc_parser *parser, struct pkg *pkg,
                       char *buf, size_t buf_len);
-ssize_t read_desc(struct archive *archive, struct pkg *pkg);
+ssize_t read_desc(struct archive *archive, struct pkg *pkg, enum pkg_entry unknown);
+
+/* Each section is written as its %HEADER% line, one line per value and
+ * a blank line to close it, the layout repo-add uses. Empty fields are
+ * left out entirely, and any newline inside a value is written as a
+ * space so it can't break that structure. Fields read from a database
+ * that repose doesn't recognize are written back at the end of the
+ * member they came from. */
+void write_desc(struct buffer *buf, const struct pkg *pkg);
+void write_depends(struct buffer *buf, const struct pkg *pkg);
+void write_files(struct buffer *buf, const struct pkg *pkg);
+void write_deltas(struct buffer *buf, const struct pkg *pkg);
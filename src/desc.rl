@@ -2,6 +2,7 @@
 
 #include <err.h>
 #include "package.h"
+#include "buffer.h"
 #include "util.h"
 
 %%{
@@ -23,6 +24,20 @@
         package_set(pkg, parser->entry, entry, entry_len);
     }
 
+    action unknown {
+        /* A files database made by repo-add repeats desc, so what this
+           member holds replaces what an earlier read of it left behind */
+        if (!parser->cleared_unknown) {
+            package_clear_unknown(pkg, parser->unknown);
+            parser->cleared_unknown = true;
+        }
+
+        parser->store[parser->pos] = 0;
+        package_set(pkg, parser->unknown, parser->store, parser->pos);
+        parser->pos = 0;
+        parser->entry = parser->unknown;
+    }
+
     header = '%FILENAME%'     %{ parser->entry = PKG_FILENAME; }
            | '%NAME%'         %{ parser->entry = PKG_PKGNAME; }
            | '%BASE%'         %{ parser->entry = PKG_PKGBASE; }
@@ -46,9 +61,14 @@
            | '%MAKEDEPENDS%'  %{ parser->entry = PKG_MAKEDEPENDS; }
            | '%CHECKDEPENDS%' %{ parser->entry = PKG_CHECKDEPENDS; }
            | '%FILES%'        %{ parser->entry = PKG_FILES; }
-           | '%DELTAS%'       %{ parser->entry = PKG_FILES; };
+           | '%DELTAS%'       %{ parser->entry = PKG_DELTAS; };
 
-      section = header '\n';
+    # Any other header is a field from a newer pacman. Both alternatives
+    # store the header as it's read, so a known one throws it away.
+    known = header %{ parser->pos = 0; };
+    unknown = ( '%' [A-Z0-9_]+ '%' ) - header;
+
+      section = ( known | unknown %unknown ) $store '\n';
       contents = [^%\n]+ @store %emit '\n';
 
       main := ( section contents* '\n' | '\n' )*;
@@ -58,7 +78,7 @@
 
 void desc_parser_init(struct desc_parser *parser)
 {
-    *parser = (struct desc_parser){0};
+    *parser = (struct desc_parser){ .unknown = PKG_UNKNOWN_DESC };
     %%access parser->;
     %%write init;
 }
@@ -79,12 +99,13 @@ ssize_t desc_parser_feed(struct desc_parser *parser, struct pkg *pkg,
     return buf_len;
 }
 
-ssize_t read_desc(struct archive *archive, struct pkg *pkg)
+ssize_t read_desc(struct archive *archive, struct pkg *pkg, enum pkg_entry unknown)
 {
     char *buf;
     ssize_t nbytes_r = 0;
     struct desc_parser parser;
     desc_parser_init(&parser);
+    parser.unknown = unknown;
 
     for (;;) {
         size_t bufsize;
@@ -102,3 +123,129 @@ ssize_t read_desc(struct archive *archive, struct pkg *pkg)
 
     return nbytes_r;
 }
+
+/* A newline ends a value, so one embedded in it (a description set by
+   hand, say) would split the entry or end the section early. Flatten
+   them into spaces instead. */
+static void write_value(struct buffer *buf, const char *str)
+{
+    for (;;) {
+        const size_t len = strcspn(str, "\n");
+        buffer_printf(buf, "%.*s", (int)len, str);
+        if (!str[len])
+            break;
+
+        buffer_putc(buf, ' ');
+        str += len + 1;
+    }
+
+    buffer_putc(buf, '\n');
+}
+
+static void write_list(struct buffer *buf, const char *header, const alpm_list_t *lst)
+{
+    if (lst == NULL)
+        return;
+
+    buffer_printf(buf, "%%%s%%\n", header);
+    for (; lst; lst = lst->next)
+        write_value(buf, lst->data);
+    buffer_putc(buf, '\n');
+}
+
+static void write_string(struct buffer *buf, const char *header, const char *str)
+{
+    if (str == NULL)
+        return;
+
+    buffer_printf(buf, "%%%s%%\n", header);
+    write_value(buf, str);
+    buffer_putc(buf, '\n');
+}
+
+static void write_size(struct buffer *buf, const char *header, size_t val)
+{
+    buffer_printf(buf, "%%%s%%\n%zd\n\n", header, val);
+}
+
+static void write_time(struct buffer *buf, const char *header, time_t val)
+{
+    buffer_printf(buf, "%%%s%%\n%ld\n\n", header, val);
+}
+
+/* Unknown fields are kept as a flat list of each header followed by its
+   values. A value can never contain a '%', so one that starts with it
+   is the next header. */
+static void write_unknown(struct buffer *buf, const alpm_list_t *lst)
+{
+    if (lst == NULL)
+        return;
+
+    const alpm_list_t *node;
+    for (node = lst; node; node = node->next) {
+        const char *entry = node->data;
+
+        if (entry[0] == '%' && node != lst)
+            buffer_putc(buf, '\n');
+        buffer_printf(buf, "%s\n", entry);
+    }
+    buffer_putc(buf, '\n');
+}
+
+#define write_entry(buf, header, val) _Generic((val), \
+    alpm_list_t *: write_list, \
+    char *: write_string, \
+    size_t: write_size, \
+    time_t: write_time)(buf, header, val)
+
+void write_desc(struct buffer *buf, const struct pkg *pkg)
+{
+    write_entry(buf, "FILENAME",  pkg->filename);
+    write_entry(buf, "NAME",      pkg->name);
+    /* makepkg may leave out pkgbase for a package that isn't split,
+       where it's simply the package's own name */
+    write_entry(buf, "BASE",      pkg->base ? pkg->base : pkg->name);
+    write_entry(buf, "VERSION",   pkg->version);
+    write_entry(buf, "DESC",      pkg->desc);
+    write_entry(buf, "GROUPS",    pkg->groups);
+    write_entry(buf, "CSIZE",     pkg->size);
+    write_entry(buf, "ISIZE",     pkg->isize);
+
+    if (pkg->base64sig)
+        write_entry(buf, "PGPSIG", pkg->base64sig);
+    else
+        write_entry(buf, "SHA256SUM", pkg->sha256sum);
+
+    write_entry(buf, "URL",       pkg->url);
+    write_entry(buf, "LICENSE",   pkg->licenses);
+    write_entry(buf, "ARCH",      pkg->arch);
+    write_entry(buf, "BUILDDATE", pkg->builddate);
+    write_entry(buf, "PACKAGER",  pkg->packager);
+    write_entry(buf, "REPLACES",  pkg->replaces);
+
+    /* Whatever a newer pacman added that we don't know about, as we
+       found it */
+    write_unknown(buf, pkg->unknown_desc);
+}
+
+void write_depends(struct buffer *buf, const struct pkg *pkg)
+{
+    write_entry(buf, "DEPENDS",      pkg->depends);
+    write_entry(buf, "CONFLICTS",    pkg->conflicts);
+    write_entry(buf, "PROVIDES",     pkg->provides);
+    write_entry(buf, "OPTDEPENDS",   pkg->optdepends);
+    write_entry(buf, "MAKEDEPENDS",  pkg->makedepends);
+    write_entry(buf, "CHECKDEPENDS", pkg->checkdepends);
+    write_unknown(buf, pkg->unknown_depends);
+}
+
+void write_files(struct buffer *buf, const struct pkg *pkg)
+{
+    write_entry(buf, "FILES", pkg->files);
+    write_unknown(buf, pkg->unknown_files);
+}
+
+void write_deltas(struct buffer *buf, const struct pkg *pkg)
+{
+    write_entry(buf, "DELTAS", pkg->deltas);
+}
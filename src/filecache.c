@@ -3,16 +3,20 @@
 #include <stdlib.h>
 #include <stdio.h>
 #include <stdbool.h>
+#include <stdarg.h>
 #include <string.h>
 #include <fcntl.h>
 #include <unistd.h>
 #include <dirent.h>
 #include <errno.h>
+#include <err.h>
 #include <alpm.h>
 
 #include "package.h"
 #include "pkgcache.h"
+#include "pkginfo.h"
 #include "filters.h"
+#include "repose.h"
 #include "util.h"
 
 static inline bool is_file(int d_type)
@@ -20,7 +24,18 @@ static inline bool is_file(int d_type)
     return d_type == DT_REG || d_type == DT_UNKNOWN;
 }
 
-static inline struct pkgcache *filecache_add(struct pkgcache *cache, struct pkg *pkg)
+/* Drop a package that lost out to another file in the pool. Files that
+   lost to a newer version of the same package are remembered, they're
+   the only ones --vacuum considers stale. */
+static inline void discard(struct pkg *pkg, alpm_list_t **superseded)
+{
+    if (superseded)
+        *superseded = alpm_list_add(*superseded, strdup(pkg->filename));
+    package_free(pkg);
+}
+
+static inline struct pkgcache *filecache_add(struct pkgcache *cache, struct pkg *pkg,
+                                             alpm_list_t **superseded)
 {
     struct pkg *old = pkgcache_find(cache, pkg->name);
     if (!old) {
@@ -28,8 +43,21 @@ static inline struct pkgcache *filecache_add(struct pkgcache *cache, struct pkg
     }
 
     int vercmp = alpm_pkg_vercmp(pkg->version, old->version);
+    alpm_list_t **stale = vercmp != 0 ? superseded : NULL;
+
+    if (vercmp == 0 && package_arch_is_any(pkg) != package_arch_is_any(old)) {
+        /* The same release was found built both for our architecture
+           and for 'any'. They aren't duplicates of each other, and the
+           database can only hold one, so consistently prefer the
+           architecture specific build regardless of scan order. */
+        vercmp = package_arch_is_any(pkg) ? -1 : 1;
+    }
+
     if (vercmp == 0 || vercmp == 1) {
-        return pkgcache_replace(cache, pkg, old);
+        cache = pkgcache_replace(cache, pkg, old);
+        discard(old, stale);
+    } else {
+        discard(pkg, stale);
     }
 
     return cache;
@@ -49,8 +77,26 @@ static size_t get_filecache_size(DIR *dirp)
     return size;
 }
 
+/* Problems we can paper over, unless we've been asked to be strict */
+static void _printf_(2, 3) lenient(const struct pkg *pkg, const char *fmt, ...)
+{
+    _cleanup_free_ char *msg = NULL;
+    va_list ap;
+
+    va_start(ap, fmt);
+    check_posix(vasprintf(&msg, fmt, ap), "failed to allocate memory");
+    va_end(ap);
+
+    if (config.strict)
+        errx(EXIT_FAILURE, "%s: %s", pkg->filename, msg);
+    trace("%s: %s\n", pkg->filename, msg);
+}
+
 static struct pkg *load_from_file(int dirfd, const char *filename)
 {
+    struct timespec start;
+    timer_start(&start);
+
     _cleanup_close_ int pkgfd = openat(dirfd, filename, O_RDONLY);
     check_posix(pkgfd, "failed to open %s", filename);
 
@@ -58,6 +104,20 @@ static struct pkg *load_from_file(int dirfd, const char *filename)
     *pkg = (struct pkg){ .filename = strdup(filename) };
 
     if (load_package(pkg, pkgfd) < 0) {
+        if (errno == EIO) {
+            if (config.strict)
+                errx(EXIT_FAILURE, "%s: package is truncated or corrupt", filename);
+            warning("%s: package is truncated or corrupt, skipping", filename);
+        } else if (errno == EFBIG) {
+            if (config.strict)
+                errx(EXIT_FAILURE, "%s: .PKGINFO is larger than %d bytes", filename, PKGINFO_MAX);
+            warning("%s: .PKGINFO is larger than %d bytes, skipping", filename, PKGINFO_MAX);
+        } else if (errno == ENOPKG) {
+            /* Source tarballs and the like can share a pool with
+               packages, so this isn't worth a warning */
+            trace("%s: archive has no .PKGINFO, not a package\n", filename);
+        }
+
         package_free(pkg);
         return NULL;
     }
@@ -67,11 +127,77 @@ static struct pkg *load_from_file(int dirfd, const char *filename)
         return NULL;
     }
 
+    debug("read %s (%zu bytes) in %.3fs\n", filename, pkg->size, timer_elapsed(&start));
     return pkg;
 }
 
+/* makepkg names packages name-pkgver-pkgrel-arch.pkg.tar[.ext]. Find
+   the architecture in that, if the file follows the convention. */
+static const char *filename_arch(const char *filename, int *len)
+{
+    const char *ext = strstr(filename, ".pkg.tar");
+    if (!ext)
+        return NULL;
+
+    const char *dash = memrchr(filename, '-', ext - filename);
+    if (!dash)
+        return NULL;
+
+    *len = ext - dash - 1;
+    return dash + 1;
+}
+
+/* A package pacman would refuse to install. Fatal, unless we've been
+   told to build what we can out of the rest of the pool. */
+static void _printf_(2, 3) invalid(const struct pkg *pkg, const char *fmt, ...)
+{
+    _cleanup_free_ char *msg = NULL;
+    va_list ap;
+
+    va_start(ap, fmt);
+    check_posix(vasprintf(&msg, fmt, ap), "failed to allocate memory");
+    va_end(ap);
+
+    if (!config.skip_invalid || config.strict)
+        errx(EXIT_FAILURE, "%s: %s", pkg->filename, msg);
+    warning("%s: %s, skipping", pkg->filename, msg);
+}
+
+static bool check_package(const struct pkg *pkg)
+{
+    const int invalid_fields = package_validate(pkg);
+    if (invalid_fields & PKG_INVALID_NAME) {
+        invalid(pkg, "invalid package name %s", pkg->name);
+        return false;
+    }
+    if (invalid_fields & PKG_INVALID_VERSION) {
+        invalid(pkg, "invalid package version %s", pkg->version);
+        return false;
+    }
+
+    if (!pkg->arch) {
+        lenient(pkg, "no architecture specified");
+    } else {
+        /* The .PKGINFO is what we publish, but a filename that
+           disagrees with it usually means the package was mislabeled */
+        int len;
+        const char *arch = filename_arch(pkg->filename, &len);
+        if (arch && ((size_t)len != strlen(pkg->arch) || !strneq(arch, pkg->arch, len)))
+            lenient(pkg, "named for %.*s but built for %s", len, arch, pkg->arch);
+    }
+
+    const alpm_list_t *node;
+    for (node = pkg->dropped; node; node = node->next) {
+        lenient(pkg, "not publishing %s, it isn't part of the database format",
+                (const char *)node->data);
+    }
+
+    return true;
+}
+
 static struct pkgcache *scan_for_targets(struct pkgcache *cache, int dirfd, DIR *dirp,
-                                        alpm_list_t *targets, const char *arch)
+                                        alpm_list_t *targets, alpm_list_t *excludes,
+                                        const char *arch, alpm_list_t **superseded)
 {
     const struct dirent *dp;
 
@@ -79,6 +205,12 @@ static struct pkgcache *scan_for_targets(struct pkgcache *cache, int dirfd, DIR
         if (!is_file(dp->d_type))
             continue;
 
+        if (excludes && match_filename(dp->d_name, excludes))
+            continue;
+
+        if (targets && !filename_may_match_targets(dp->d_name, targets))
+            continue;
+
         struct pkg *pkg = load_from_file(dirfd, dp->d_name);
         if (!pkg)
             continue;
@@ -89,17 +221,24 @@ static struct pkgcache *scan_for_targets(struct pkgcache *cache, int dirfd, DIR
         }
 
         if (arch && !match_arch(pkg, arch)) {
+            lenient(pkg, "built for %s, not %s, skipping", pkg->arch, arch);
+            package_free(pkg);
+            continue;
+        }
+
+        if (!check_package(pkg)) {
             package_free(pkg);
             continue;
         }
 
-        cache = filecache_add(cache, pkg);
+        cache = filecache_add(cache, pkg, superseded);
     }
 
     return cache;
 }
 
-struct pkgcache *get_filecache(int dirfd, alpm_list_t *targets, const char *arch)
+struct pkgcache *get_filecache(int dirfd, alpm_list_t *targets, alpm_list_t *excludes,
+                               const char *arch, alpm_list_t **superseded)
 {
     int dupfd = dup(dirfd);
     check_posix(dupfd, "failed to duplicate fd");
@@ -111,5 +250,5 @@ struct pkgcache *get_filecache(int dirfd, alpm_list_t *targets, const char *arch
     size_t size = get_filecache_size(dirp);
     struct pkgcache *cache = pkgcache_create(size);
 
-    return scan_for_targets(cache, dirfd, dirp, targets, arch);
+    return scan_for_targets(cache, dirfd, dirp, targets, excludes, arch, superseded);
 }
@@ -1,6 +1,8 @@
 #pragma once
 
+#include <stdbool.h>
 #include <alpm_list.h>
 #include "pkgcache.h"
 
-struct pkgcache *get_filecache(int dirfd, alpm_list_t *targets, const char *arch);
+struct pkgcache *get_filecache(int dirfd, alpm_list_t *targets, alpm_list_t *excludes,
+                               const char *arch, alpm_list_t **superseded);
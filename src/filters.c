@@ -1,5 +1,6 @@
 #include "filters.h"
 
+#include <string.h>
 #include <fnmatch.h>
 #include "package.h"
 #include "util.h"
@@ -23,3 +24,50 @@ bool match_targets(struct pkg *pkg, alpm_list_t *targets)
 
     return false;
 }
+
+bool match_target_one(struct pkg *pkg, const char *target)
+{
+    _cleanup_free_ char *fullname = joinstring(pkg->name, "-", pkg->version, NULL);
+    return match_target(pkg, target, fullname);
+}
+
+/* makepkg names packages name-pkgver-pkgrel-arch.pkg.tar[.ext] */
+static bool is_makepkg_filename(const char *filename)
+{
+    const char *end = strstr(filename, ".pkg.tar");
+    for (int i = 0; end && i < 3; ++i)
+        end = memrchr(filename, '-', end - filename);
+    return end && end != filename;
+}
+
+/* A package named by makepkg starts with its name and full version, so
+   any target that can match the package will also match a prefix of its
+   filename. This lets us skip reading files that can't be targets. Files
+   named any other way could be anything, so they still have to be read. */
+bool filename_may_match_targets(const char *filename, alpm_list_t *targets)
+{
+    const alpm_list_t *node;
+
+    if (!is_makepkg_filename(filename))
+        return true;
+
+    for (node = targets; node; node = node->next) {
+        _cleanup_free_ char *prefix = joinstring(node->data, "*", NULL);
+        if (fnmatch(prefix, filename, 0) == 0)
+            return true;
+    }
+
+    return false;
+}
+
+bool match_filename(const char *filename, alpm_list_t *patterns)
+{
+    const alpm_list_t *node;
+
+    for (node = patterns; node; node = node->next) {
+        if (fnmatch(node->data, filename, 0) == 0)
+            return true;
+    }
+
+    return false;
+}
@@ -7,10 +7,13 @@
 #include "util.h"
 
 bool match_targets(struct pkg *pkg, alpm_list_t *targets);
+bool match_target_one(struct pkg *pkg, const char *target);
+bool match_filename(const char *filename, alpm_list_t *patterns);
+bool filename_may_match_targets(const char *filename, alpm_list_t *targets);
 
 static inline bool match_arch(struct pkg *pkg, const char *arch)
 {
     if (!pkg->arch)
         return arch != NULL;
-    return streq(pkg->arch, arch) || streq(pkg->arch, "any");
+    return streq(pkg->arch, arch) || package_arch_is_any(pkg);
 }
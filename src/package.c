@@ -3,12 +3,14 @@
 #include <stdlib.h>
 #include <stdbool.h>
 #include <string.h>
+#include <errno.h>
 #include <err.h>
 #include <archive.h>
 #include <archive_entry.h>
 #include <unistd.h>
 #include <fcntl.h>
 #include <sys/stat.h>
+#include <alpm.h>
 
 #include "util.h"
 #include "pkginfo.h"
@@ -28,23 +30,38 @@ int load_package(pkg_t *pkg, int fd)
 
     if (archive_read_open_fd(archive, fd, 8192) != ARCHIVE_OK) {
         archive_read_free(archive);
+        errno = EINVAL;
         return -1;
     }
 
-    bool found_pkginfo = false;
+    bool found_pkginfo = false, corrupt = false, too_big = false;
     struct archive_entry *entry;
-    while (archive_read_next_header(archive, &entry) == ARCHIVE_OK && !found_pkginfo) {
+    int status = ARCHIVE_OK;
+    while (!found_pkginfo && !corrupt && !too_big &&
+           (status = archive_read_next_header(archive, &entry)) == ARCHIVE_OK) {
         const char *entry_name = archive_entry_pathname(entry);
         const mode_t mode = archive_entry_mode(entry);
 
         if (S_ISREG(mode) && streq(entry_name, ".PKGINFO")) {
             if (read_pkginfo(archive, pkg) < 0) {
+                if (errno == EIO) {
+                    corrupt = true;
+                    continue;
+                } else if (errno == EFBIG) {
+                    too_big = true;
+                    continue;
+                }
                 errx(EXIT_FAILURE, "failed to parse PKGINFO on %s", pkg->filename);
             }
             found_pkginfo = true;
         }
     }
 
+    /* The archive was recognized, but the decompressor or tar reader
+       gave up on it partway through: the file is truncated or corrupt */
+    if (!found_pkginfo && status < ARCHIVE_WARN)
+        corrupt = true;
+
     archive_read_close(archive);
     archive_read_free(archive);
 
@@ -55,6 +72,10 @@ int load_package(pkg_t *pkg, int fd)
         return 0;
     }
 
+    if (too_big)
+        errno = EFBIG;
+    else
+        errno = corrupt ? EIO : ENOPKG;
     return -1;
 }
 
@@ -99,16 +120,33 @@ int load_package_files(struct pkg *pkg, int fd)
         return -1;
     }
 
+    bool unsafe = false;
     struct archive_entry *entry;
-    while (archive_read_next_header(archive, &entry) == ARCHIVE_OK) {
+    while (!unsafe && archive_read_next_header(archive, &entry) == ARCHIVE_OK) {
         const char *entry_name = archive_entry_pathname(entry);
 
+        /* pacman refuses to extract these, but we'd happily publish
+           them in the files database */
+        if (!path_is_safe(entry_name)) {
+            unsafe = true;
+            continue;
+        }
+
         if (entry_name[0] != '.')
             pkg->files = alpm_list_add(pkg->files, strdup(entry_name));
     }
 
     archive_read_close(archive);
     archive_read_free(archive);
+
+    if (unsafe) {
+        alpm_list_free_inner(pkg->files, free);
+        alpm_list_free(pkg->files);
+        pkg->files = NULL;
+        errno = EPERM;
+        return -1;
+    }
+
     return 0;
 }
 
@@ -123,6 +161,7 @@ void package_free(pkg_t *pkg)
     free(pkg->sha256sum);
     free(pkg->base64sig);
     free(pkg->arch);
+    free(pkg->pkgtype);
 
     alpm_list_free_inner(pkg->groups, free);
     alpm_list_free(pkg->groups);
@@ -140,6 +179,24 @@ void package_free(pkg_t *pkg)
     alpm_list_free(pkg->makedepends);
     alpm_list_free_inner(pkg->files, free);
     alpm_list_free(pkg->files);
+    alpm_list_free_inner(pkg->deltas, free);
+    alpm_list_free(pkg->deltas);
+    alpm_list_free_inner(pkg->makepkgopts, free);
+    alpm_list_free(pkg->makepkgopts);
+    alpm_list_free_inner(pkg->validations, free);
+    alpm_list_free(pkg->validations);
+    alpm_list_free_inner(pkg->backups, free);
+    alpm_list_free(pkg->backups);
+    alpm_list_free_inner(pkg->dropped, free);
+    alpm_list_free(pkg->dropped);
+    alpm_list_free_inner(pkg->unknown_desc, free);
+    alpm_list_free(pkg->unknown_desc);
+    alpm_list_free_inner(pkg->unknown_depends, free);
+    alpm_list_free(pkg->unknown_depends);
+    alpm_list_free_inner(pkg->unknown_files, free);
+    alpm_list_free(pkg->unknown_files);
+    alpm_list_free_inner(pkg->entries, free);
+    alpm_list_free(pkg->entries);
 
     free(pkg);
 }
@@ -165,6 +222,12 @@ static void pkg_set_time(const char *entry, size_t _unused_ len, time_t *data)
     parse_time(entry, data);
 }
 
+static void pkg_drop_field(const char *field, alpm_list_t **dropped)
+{
+    if (!alpm_list_find_str(*dropped, field))
+        *dropped = alpm_list_add(*dropped, strdup(field));
+}
+
 #define pkg_set(entry, len, field) _Generic((field), \
     alpm_list_t **: pkg_append_list, \
     char **: pkg_set_string, \
@@ -254,7 +317,325 @@ void package_set(pkg_t *pkg, enum pkg_entry type, const char *entry, size_t len)
     case PKG_DELTAS:
         pkg_set(entry, len, &pkg->deltas);
         break;
+    case PKG_MAKEPKGOPT:
+        pkg_set(entry, len, &pkg->makepkgopts);
+        break;
+    case PKG_VALIDATION:
+        pkg_set(entry, len, &pkg->validations);
+        break;
+    case PKG_BACKUP:
+        pkg_set(entry, len, &pkg->backups);
+        break;
+    case PKG_XDATA: {
+        /* Extra key=value data. The package type is the only key
+           makepkg writes so far */
+        static const char pkgtype[] = "pkgtype=";
+        const size_t prefix = sizeof(pkgtype) - 1;
+
+        if (len > prefix && strneq(entry, pkgtype, prefix))
+            pkg_set(entry + prefix, len - prefix, &pkg->pkgtype);
+        break;
+    }
+    case PKG_BUILDDIR:
+        pkg_drop_field("builddir", &pkg->dropped);
+        break;
+    case PKG_BUILDENV:
+        pkg_drop_field("buildenv", &pkg->dropped);
+        break;
+    case PKG_INSTALLED:
+        pkg_drop_field("installed", &pkg->dropped);
+        break;
+    case PKG_PKGBUILD_SHA256SUM:
+        pkg_drop_field("pkgbuild_sha256sum", &pkg->dropped);
+        break;
+    case PKG_UNKNOWN_DESC:
+        pkg_set(entry, len, &pkg->unknown_desc);
+        break;
+    case PKG_UNKNOWN_DEPENDS:
+        pkg_set(entry, len, &pkg->unknown_depends);
+        break;
+    case PKG_UNKNOWN_FILES:
+        pkg_set(entry, len, &pkg->unknown_files);
+        break;
     default:
         break;
     }
 }
+
+void package_clear_unknown(pkg_t *pkg, enum pkg_entry type)
+{
+    alpm_list_t **list;
+
+    switch (type) {
+    case PKG_UNKNOWN_DESC:
+        list = &pkg->unknown_desc;
+        break;
+    case PKG_UNKNOWN_DEPENDS:
+        list = &pkg->unknown_depends;
+        break;
+    case PKG_UNKNOWN_FILES:
+        list = &pkg->unknown_files;
+        break;
+    default:
+        return;
+    }
+
+    alpm_list_free_inner(*list, free);
+    alpm_list_free(*list);
+    *list = NULL;
+}
+
+static bool is_number(const char *str, size_t len)
+{
+    return len && strspn(str, "0123456789") >= len;
+}
+
+/* Package names follow the same rules makepkg enforces: alphanumerics
+ * and @._+- but never leading with a hyphen or dot. */
+static bool valid_name(const char *name)
+{
+    static const char charset[] = "abcdefghijklmnopqrstuvwxyz"
+                                  "ABCDEFGHIJKLMNOPQRSTUVWXYZ"
+                                  "0123456789@._+-";
+
+    if (!name || !name[0] || name[0] == '-' || name[0] == '.')
+        return false;
+    return name[strspn(name, charset)] == '\0';
+}
+
+/* Versions take the form [epoch:]pkgver-pkgrel, where the epoch is a
+ * number, pkgrel is a number optionally followed by a minor number and
+ * pkgver may contain anything but colons, slashes, hyphens and spaces. */
+static bool valid_version(const char *version)
+{
+    if (!version)
+        return false;
+
+    const char *pkgrel = strrchr(version, '-');
+    if (!pkgrel || pkgrel == version)
+        return false;
+
+    const char *pkgver = version, *end = pkgrel++;
+    const char *colon = memchr(version, ':', end - version);
+    if (colon) {
+        if (!is_number(version, colon - version))
+            return false;
+        pkgver = colon + 1;
+    }
+
+    const size_t pkgver_len = end - pkgver;
+    if (!pkgver_len || strcspn(pkgver, ":/- \t") < pkgver_len)
+        return false;
+
+    const char *minor = strchr(pkgrel, '.');
+    if (minor)
+        return is_number(pkgrel, minor - pkgrel) && is_number(minor + 1, strlen(minor + 1));
+    return is_number(pkgrel, strlen(pkgrel));
+}
+
+int package_validate(const pkg_t *pkg)
+{
+    int invalid = 0;
+
+    if (!valid_name(pkg->name))
+        invalid |= PKG_INVALID_NAME;
+    if (!valid_version(pkg->version))
+        invalid |= PKG_INVALID_VERSION;
+
+    return invalid;
+}
+
+bool package_arch_is_any(const pkg_t *pkg)
+{
+    return pkg->arch && streq(pkg->arch, "any");
+}
+
+/* Newer makepkg says outright what kind of package it built. Older
+ * packages can only be recognized by the name makepkg gives them. */
+bool package_is_debug(const pkg_t *pkg)
+{
+    if (pkg->pkgtype)
+        return streq(pkg->pkgtype, "debug");
+
+    static const char suffix[] = "-debug";
+    const size_t len = strlen(pkg->name);
+
+    return len > sizeof(suffix) - 1 &&
+        streq(pkg->name + len - (sizeof(suffix) - 1), suffix);
+}
+
+/* Look up how makepkg treated a build option: 1 if it was enabled, 0 if
+ * it was disabled (recorded as !option) and -1 if it wasn't recorded. */
+int package_build_option(const pkg_t *pkg, const char *option)
+{
+    const alpm_list_t *node;
+
+    for (node = pkg->makepkgopts; node; node = node->next) {
+        const char *opt = node->data;
+        const bool negated = opt[0] == '!';
+
+        if (streq(opt + negated, option))
+            return !negated;
+    }
+
+    return -1;
+}
+
+enum depmod {
+    DEPMOD_ANY,
+    DEPMOD_EQ,
+    DEPMOD_GE,
+    DEPMOD_LE,
+    DEPMOD_GT,
+    DEPMOD_LT
+};
+
+/* Dependencies and provisions share the form name[<op>version] */
+struct depend {
+    const char *name;
+    size_t name_len;
+    enum depmod mod;
+    const char *version;
+};
+
+static void parse_depend(const char *str, struct depend *dep)
+{
+    static const struct {
+        const char *op;
+        enum depmod mod;
+    } ops[] = {
+        { ">=", DEPMOD_GE },
+        { "<=", DEPMOD_LE },
+        { "=",  DEPMOD_EQ },
+        { ">",  DEPMOD_GT },
+        { "<",  DEPMOD_LT },
+    };
+
+    *dep = (struct depend){
+        .name = str,
+        .name_len = strcspn(str, "<>="),
+    };
+
+    const char *op = str + dep->name_len;
+    for (size_t i = 0; i < sizeof(ops) / sizeof(*ops); ++i) {
+        const size_t len = strlen(ops[i].op);
+        if (strneq(op, ops[i].op, len)) {
+            dep->mod = ops[i].mod;
+            dep->version = op + len;
+            break;
+        }
+    }
+}
+
+static bool version_satisfies(const char *version, const struct depend *dep)
+{
+    const int cmp = alpm_pkg_vercmp(version, dep->version);
+
+    switch (dep->mod) {
+    case DEPMOD_ANY:
+        return true;
+    case DEPMOD_EQ:
+        return cmp == 0;
+    case DEPMOD_GE:
+        return cmp >= 0;
+    case DEPMOD_LE:
+        return cmp <= 0;
+    case DEPMOD_GT:
+        return cmp > 0;
+    case DEPMOD_LT:
+        return cmp < 0;
+    }
+
+    return false;
+}
+
+static bool depend_name_eq(const struct depend *dep, const char *name, size_t len)
+{
+    return dep->name_len == len && strneq(dep->name, name, len);
+}
+
+/* Does this package satisfy a dependency like "foo>=1.0", either by
+ * name or through what it provides? Like pacman, a provision that
+ * carries no version only satisfies dependencies that don't ask for
+ * one. */
+bool package_satisfies(const pkg_t *pkg, const char *depend)
+{
+    struct depend dep;
+    parse_depend(depend, &dep);
+
+    if (pkg->name && depend_name_eq(&dep, pkg->name, strlen(pkg->name)) &&
+        pkg->version && version_satisfies(pkg->version, &dep))
+        return true;
+
+    const alpm_list_t *node;
+    for (node = pkg->provides; node; node = node->next) {
+        struct depend provide;
+        parse_depend(node->data, &provide);
+
+        if (!depend_name_eq(&dep, provide.name, provide.name_len))
+            continue;
+
+        if (dep.mod == DEPMOD_ANY)
+            return true;
+        if (provide.mod == DEPMOD_EQ && version_satisfies(provide.version, &dep))
+            return true;
+    }
+
+    return false;
+}
+
+/* pacman finds a package's entry in the database by this name alone */
+bool package_entry_matches(const pkg_t *pkg, const char *entry)
+{
+    if (!pkg->name || !pkg->version)
+        return false;
+
+    const size_t len = strlen(pkg->name);
+    return strneq(entry, pkg->name, len) && entry[len] == '-' &&
+        streq(entry + len + 1, pkg->version);
+}
+
+static const alpm_list_t *package_depends(const pkg_t *pkg, enum pkg_entry kind)
+{
+    switch (kind) {
+    case PKG_DEPENDS:
+        return pkg->depends;
+    case PKG_MAKEDEPENDS:
+        return pkg->makedepends;
+    case PKG_CHECKDEPENDS:
+        return pkg->checkdepends;
+    default:
+        return NULL;
+    }
+}
+
+/* Does the package have a dependency of this kind on name, whatever
+ * version it asks for? */
+bool package_depends_on(const pkg_t *pkg, enum pkg_entry kind, const char *name)
+{
+    const size_t len = strlen(name);
+
+    const alpm_list_t *node;
+    for (node = package_depends(pkg, kind); node; node = node->next) {
+        struct depend dep;
+        parse_depend(node->data, &dep);
+
+        if (depend_name_eq(&dep, name, len))
+            return true;
+    }
+
+    return false;
+}
+
+/* Does the package have a dependency of this kind that target actually
+ * satisfies, honouring version constraints and what target provides? */
+bool package_requires(const pkg_t *pkg, enum pkg_entry kind, const pkg_t *target)
+{
+    const alpm_list_t *node;
+    for (node = package_depends(pkg, kind); node; node = node->next) {
+        if (package_satisfies(target, node->data))
+            return true;
+    }
+
+    return false;
+}
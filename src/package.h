@@ -2,6 +2,7 @@
 
 #include <stddef.h>
 #include <stdint.h>
+#include <stdbool.h>
 #include <time.h>
 #include <alpm_list.h>
 
@@ -33,7 +34,21 @@ enum pkg_entry {
     PKG_FILES,
     PKG_BACKUP,
     PKG_DELTAS,
-    PKG_MAKEPKGOPT
+    PKG_MAKEPKGOPT,
+    PKG_BUILDDIR,
+    PKG_BUILDENV,
+    PKG_INSTALLED,
+    PKG_PKGBUILD_SHA256SUM,
+    PKG_VALIDATION,
+    PKG_XDATA,
+    PKG_UNKNOWN_DESC,
+    PKG_UNKNOWN_DEPENDS,
+    PKG_UNKNOWN_FILES
+};
+
+enum pkg_invalid {
+    PKG_INVALID_NAME    = 1,
+    PKG_INVALID_VERSION = 1 << 1
 };
 
 typedef struct pkg {
@@ -48,6 +63,7 @@ typedef struct pkg {
     char *sha256sum;
     char *base64sig;
     char *arch;
+    char *pkgtype;
     size_t size;
     size_t isize;
     time_t builddate;
@@ -64,10 +80,44 @@ typedef struct pkg {
     alpm_list_t *checkdepends;
     alpm_list_t *files;
     alpm_list_t *deltas;
+    alpm_list_t *makepkgopts;
+    alpm_list_t *validations;
+    alpm_list_t *backups;
+
+    /* Fields we parsed but which have no place in the database */
+    alpm_list_t *dropped;
+
+    /* Database fields we don't recognize, each "%HEADER%" followed by
+       its values, kept by the member they were found in so they can be
+       written back out to it untouched */
+    alpm_list_t *unknown_desc;
+    alpm_list_t *unknown_depends;
+    alpm_list_t *unknown_files;
+
+    /* The database directories this package's desc was read from. A
+       healthy database has exactly one, named %NAME%-%VERSION% */
+    alpm_list_t *entries;
 } pkg_t;
 
+/* Fails with errno set to EINVAL if the file isn't an archive at all,
+ * ENOPKG if it's an archive without a .PKGINFO, and EIO if the archive
+ * is truncated or corrupt. */
 int load_package(pkg_t *pkg, int fd);
 int load_package_signature(struct pkg *pkg, int fd);
+/* Fails with errno set to EPERM if any member is an absolute path or
+ * climbs out of the package with "..". */
 int load_package_files(pkg_t *pkg, int fd);
 void package_free(pkg_t *pkg);
 void package_set(pkg_t *pkg, enum pkg_entry type, const char *entry, size_t len);
+/* type is one of PKG_UNKNOWN_DESC, PKG_UNKNOWN_DEPENDS or PKG_UNKNOWN_FILES */
+void package_clear_unknown(pkg_t *pkg, enum pkg_entry type);
+int package_build_option(const pkg_t *pkg, const char *option);
+bool package_arch_is_any(const pkg_t *pkg);
+bool package_is_debug(const pkg_t *pkg);
+int package_validate(const pkg_t *pkg);
+bool package_satisfies(const pkg_t *pkg, const char *depend);
+bool package_entry_matches(const pkg_t *pkg, const char *entry);
+
+/* kind is one of PKG_DEPENDS, PKG_MAKEDEPENDS or PKG_CHECKDEPENDS */
+bool package_depends_on(const pkg_t *pkg, enum pkg_entry kind, const char *name);
+bool package_requires(const pkg_t *pkg, enum pkg_entry kind, const pkg_t *target);
@@ -325,6 +325,17 @@ void pkgcache_free(struct pkgcache *cache)
     free(cache);
 }
 
+/* Order the package list by name. The list nodes are only relinked, so
+ * the hash table pointing at them stays valid. */
+void pkgcache_sort(struct pkgcache *cache)
+{
+    if (cache == NULL) {
+        return;
+    }
+
+    cache->list = alpm_list_msort(cache->list, cache->entries, pkg_cmp);
+}
+
 struct pkg *pkgcache_find(struct pkgcache *cache, const char *name)
 {
     alpm_list_t *lp;
@@ -352,3 +363,26 @@ struct pkg *pkgcache_find(struct pkgcache *cache, const char *name)
 
 	return NULL;
 }
+
+/* Everything that can satisfy a dependency, whether by its own name or
+ * through what it provides. A bare name such as "libssl.so" matches
+ * any version, including versioned provisions like libssl.so=3-64. The
+ * caller owns the returned list, but not the packages in it. */
+alpm_list_t *pkgcache_find_provides(struct pkgcache *cache, const char *depend)
+{
+    alpm_list_t *node, *providers = NULL;
+
+    if (depend == NULL || cache == NULL) {
+        return NULL;
+    }
+
+    for (node = cache->list; node; node = node->next) {
+        struct pkg *pkg = node->data;
+
+        if (package_satisfies(pkg, depend)) {
+            providers = alpm_list_add(providers, pkg);
+        }
+    }
+
+    return providers;
+}
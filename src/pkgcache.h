@@ -24,4 +24,7 @@ struct pkgcache *pkgcache_replace(struct pkgcache *cache, struct pkg *new, struc
 struct pkgcache *pkgcache_add_sorted(struct pkgcache *cache, struct pkg *pkg);
 struct pkgcache *pkgcache_remove(struct pkgcache *cache, struct pkg *pkg, struct pkg **data);
 
+void pkgcache_sort(struct pkgcache *cache);
+
 struct pkg *pkgcache_find(struct pkgcache *cache, const char *name);
+alpm_list_t *pkgcache_find_provides(struct pkgcache *cache, const char *depend);
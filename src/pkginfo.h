@@ -5,16 +5,27 @@
 #include <limits.h>
 #include "package.h"
 
+/* Real .PKGINFO files are a few kilobytes at most. Cap how much we're
+ * willing to decompress so a hostile package can't exhaust memory. */
+#define PKGINFO_MAX 0x100000
+
 struct archive;
+struct buffer;
 
 struct pkginfo_parser {
     int cs;
     enum pkg_entry entry;
+    size_t bom_pos;
     size_t pos;
     char store[LINE_MAX];
 };
 
 void pkginfo_parser_init(struct pkginfo_parser *parser);
+
+/* Each entry is applied to the package as soon as it's parsed, and none
+ * depend on another, so entries may come in any order. Not every tool
+ * that writes a .PKGINFO follows makepkg's ordering. */
 ssize_t pkginfo_parser_feed(struct pkginfo_parser *parser, struct pkg *pkg,
                             char *buf, size_t buf_len);
 ssize_t read_pkginfo(struct archive *archive, struct pkg *pkg);
+void write_pkginfo(struct buffer *buf, const struct pkg *pkg);
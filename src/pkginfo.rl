@@ -1,20 +1,30 @@
 #include "pkginfo.h"
 
 #include <err.h>
+#include <errno.h>
+#include <string.h>
+#include <archive.h>
 #include "package.h"
+#include "buffer.h"
 #include "util.h"
 
 %%{
     machine pkginfo;
 
     action store {
+        /* A line that doesn't fit is as unparseable as a malformed one */
+        if (parser->pos == LINE_MAX - 1)
+            fgoto *pkginfo_error;
         parser->store[parser->pos++] = fc;
-        if (parser->pos == LINE_MAX) {
-            errx(1, "desc line too long");
-        }
     }
 
     action emit {
+        /* Trailing whitespace isn't part of the value either, it'd
+           only make names and dependencies fail to match later */
+        while (parser->pos && (parser->store[parser->pos - 1] == ' ' ||
+                               parser->store[parser->pos - 1] == '\t'))
+            --parser->pos;
+
         if (parser->pos) {
             const char *entry = parser->store;
             const size_t entry_len = parser->pos;
@@ -25,6 +35,9 @@
         }
     }
 
+    # Other tools sometimes pluralize the list keys makepkg writes in
+    # the singular (or vice versa for provides and replaces), so the
+    # trailing 's' is optional on those.
     header = 'pkgname'     %{ parser->entry = PKG_PKGNAME; }
            | 'pkgbase'     %{ parser->entry = PKG_PKGBASE; }
            | 'pkgver'      %{ parser->entry = PKG_VERSION; }
@@ -34,22 +47,34 @@
            | 'packager'    %{ parser->entry = PKG_PACKAGER; }
            | 'size'        %{ parser->entry = PKG_ISIZE; }
            | 'arch'        %{ parser->entry = PKG_ARCH; }
-           | 'group'       %{ parser->entry = PKG_GROUPS; }
-           | 'license'     %{ parser->entry = PKG_LICENSE; }
-           | 'replaces'    %{ parser->entry = PKG_REPLACES; }
+           | 'group' 's'?   %{ parser->entry = PKG_GROUPS; }
+           | 'license' 's'? %{ parser->entry = PKG_LICENSE; }
+           | 'replace' 's'? %{ parser->entry = PKG_REPLACES; }
            | 'depend'      %{ parser->entry = PKG_DEPENDS; }
-           | 'conflict'    %{ parser->entry = PKG_CONFLICTS; }
-           | 'provides'    %{ parser->entry = PKG_PROVIDES; }
+           | 'conflict' 's'? %{ parser->entry = PKG_CONFLICTS; }
+           | 'provide' 's'? %{ parser->entry = PKG_PROVIDES; }
            | 'optdepend'   %{ parser->entry = PKG_OPTDEPENDS; }
            | 'makedepend'  %{ parser->entry = PKG_MAKEDEPENDS; }
            | 'checkdepend' %{ parser->entry = PKG_CHECKDEPENDS; }
            | 'backup'      %{ parser->entry = PKG_BACKUP; }
-           | 'makepkgopt'  %{ parser->entry = PKG_MAKEPKGOPT; };
-
-    entry = header ' = ' [^\n]* @store %emit '\n';
+           | 'makepkgopt'  %{ parser->entry = PKG_MAKEPKGOPT; }
+           | 'builddir'    %{ parser->entry = PKG_BUILDDIR; }
+           | 'buildenv'    %{ parser->entry = PKG_BUILDENV; }
+           | 'installed'   %{ parser->entry = PKG_INSTALLED; }
+           | 'pkgbuild_sha256sum' %{ parser->entry = PKG_PKGBUILD_SHA256SUM; }
+           | 'validation'  %{ parser->entry = PKG_VALIDATION; }
+           | 'xdata'       %{ parser->entry = PKG_XDATA; };
+
+    # makepkg writes 'key = value', but be lenient about the whitespace
+    # around the '=' since other tools use tabs. Whitespace leading the
+    # value isn't part of it.
+    blank = [ \t];
+    value = ( [^ \t\n] [^\n]* ) @store;
+    entry = header blank* '=' blank* value? %emit '\n';
     comment = '#' [^\n]* '\n';
+    empty = blank* '\n';
 
-    main := ( entry | comment )*;
+    main := ( entry | comment | empty )*;
 }%%
 
 %%write data nofinal;
@@ -64,9 +89,22 @@ void pkginfo_parser_init(struct pkginfo_parser *parser)
 ssize_t pkginfo_parser_feed(struct pkginfo_parser *parser, struct pkg *pkg,
                          char *buf, size_t buf_len)
 {
+    static const unsigned char utf8_bom[] = { 0xef, 0xbb, 0xbf };
     char *p = buf;
     char *pe = p + buf_len;
 
+    /* Skip over a byte order mark some editors leave at the start of
+       the file. It's matched byte by byte since it may be split
+       across feeds. */
+    for (; parser->bom_pos < sizeof(utf8_bom) && p < pe; ++p, ++parser->bom_pos) {
+        if ((unsigned char)*p != utf8_bom[parser->bom_pos]) {
+            if (parser->bom_pos)
+                return -1;
+            parser->bom_pos = sizeof(utf8_bom);
+            break;
+        }
+    }
+
     %%access parser->;
     %%write exec;
 
@@ -86,17 +124,104 @@ ssize_t read_pkginfo(struct archive *archive, struct pkg *pkg)
 
     for (;;) {
         size_t bufsize;
-        archive_read(archive, &buf, &bufsize);
+        int status = archive_read(archive, &buf, &bufsize);
+        if (status == ARCHIVE_EOF)
+            break;
+        if (status < ARCHIVE_WARN) {
+            errno = EIO;
+            return -1;
+        }
+
+        if (bufsize > PKGINFO_MAX - (size_t)nbytes_r) {
+            errno = EFBIG;
+            return -1;
+        }
 
         ssize_t result = pkginfo_parser_feed(&parser, pkg, buf, bufsize);
         if (result < 0) {
+            errno = EBADMSG;
             return result;
         } else {
             nbytes_r += result;
         }
-
-        break;
     }
 
     return nbytes_r;
 }
+
+/* Every value has to fit on its line, so fold any embedded newlines,
+ * say from a description read out of a database, into spaces */
+static void write_value(struct buffer *buf, const char *key, const char *str)
+{
+    buffer_printf(buf, "%s = ", key);
+    for (;;) {
+        const size_t len = strcspn(str, "\n");
+        buffer_printf(buf, "%.*s", (int)len, str);
+        if (!str[len])
+            break;
+
+        buffer_putc(buf, ' ');
+        str += len + 1;
+    }
+
+    buffer_putc(buf, '\n');
+}
+
+static void write_list(struct buffer *buf, const char *key, const alpm_list_t *lst)
+{
+    for (; lst; lst = lst->next)
+        write_value(buf, key, lst->data);
+}
+
+static void write_string(struct buffer *buf, const char *key, const char *str)
+{
+    if (str)
+        write_value(buf, key, str);
+}
+
+static void write_size(struct buffer *buf, const char *key, size_t val)
+{
+    buffer_printf(buf, "%s = %zu\n", key, val);
+}
+
+static void write_time(struct buffer *buf, const char *key, time_t val)
+{
+    buffer_printf(buf, "%s = %ld\n", key, val);
+}
+
+#define write_entry(buf, key, val) _Generic((val), \
+    alpm_list_t *: write_list, \
+    char *: write_string, \
+    size_t: write_size, \
+    time_t: write_time)(buf, key, val)
+
+/* Serialize a package back into .PKGINFO form, in the same order
+ * makepkg writes its fields. */
+void write_pkginfo(struct buffer *buf, const struct pkg *pkg)
+{
+    write_entry(buf, "pkgname",     pkg->name);
+    write_entry(buf, "pkgbase",     pkg->base);
+    if (pkg->pkgtype) {
+        _cleanup_free_ char *xdata = joinstring("pkgtype=", pkg->pkgtype, NULL);
+        write_entry(buf, "xdata", xdata);
+    }
+    write_entry(buf, "pkgver",      pkg->version);
+    write_entry(buf, "pkgdesc",     pkg->desc);
+    write_entry(buf, "url",         pkg->url);
+    write_entry(buf, "builddate",   pkg->builddate);
+    write_entry(buf, "packager",    pkg->packager);
+    write_entry(buf, "size",        pkg->isize);
+    write_entry(buf, "arch",        pkg->arch);
+    write_entry(buf, "license",     pkg->licenses);
+    write_entry(buf, "replaces",    pkg->replaces);
+    write_entry(buf, "group",       pkg->groups);
+    write_entry(buf, "conflict",    pkg->conflicts);
+    write_entry(buf, "provides",    pkg->provides);
+    write_entry(buf, "backup",      pkg->backups);
+    write_entry(buf, "depend",      pkg->depends);
+    write_entry(buf, "optdepend",   pkg->optdepends);
+    write_entry(buf, "makedepend",  pkg->makedepends);
+    write_entry(buf, "checkdepend", pkg->checkdepends);
+    write_entry(buf, "makepkgopt",  pkg->makepkgopts);
+    write_entry(buf, "validation",  pkg->validations);
+}
@@ -11,6 +11,7 @@
 #include <getopt.h>
 #include <fcntl.h>
 #include <unistd.h>
+#include <dirent.h>
 #include <archive.h>
 #include <alpm.h>
 #include <alpm_list.h>
@@ -21,6 +22,8 @@
 #include <locale.h>
 
 #include "database.h"
+#include "desc.h"
+#include "buffer.h"
 #include "filecache.h"
 #include "package.h"
 #include "pkgcache.h"
@@ -29,7 +32,12 @@
 #include "base64.h"
 #include "util.h"
 
-struct config config = {0};
+/* Without a compression option or REPOSE_COMPRESSION, use zstd like
+   pacman does for packages */
+struct config config = {
+    .compression = ARCHIVE_FILTER_ZSTD,
+    .compression_level = -1
+};
 
 void trace(const char *fmt, ...)
 {
@@ -42,26 +50,74 @@ void trace(const char *fmt, ...)
     }
 }
 
+void debug(const char *fmt, ...)
+{
+    if (config.verbose > 1) {
+        va_list ap;
+
+        va_start(ap, fmt);
+        vprintf(fmt, ap);
+        va_end(ap);
+    }
+}
+
+/* Problems that don't stop us, which --quiet keeps to itself */
+void warning(const char *fmt, ...)
+{
+    if (!config.quiet) {
+        va_list ap;
+
+        va_start(ap, fmt);
+        vwarnx(fmt, ap);
+        va_end(ap);
+    }
+}
+
 static _noreturn_ void usage(FILE *out)
 {
     fprintf(out, "usage: %s [options] <database> [pkgs|deltas ...]\n", program_invocation_short_name);
     fputs("Options\n"
           " -h, --help            display this help and exit\n"
           " -V, --version         display version\n"
-          " -v, --verbose         verbose output\n"
+          " -v, --verbose         verbose output, repeat to include timings\n"
+          " -q, --quiet           only report errors\n"
           " -f, --files           also build the .files database\n"
           " -l, --list            list packages in the repository\n"
+          "     --older-than=DAYS only list packages built more than DAYS ago\n"
+          "     --stats           summarize the packages in the repository\n"
+          "     --diff=DATABASE   show how the repository changed since DATABASE\n"
+          "     --required-by=PKG list the packages that depend on PKG\n"
+          "     --bases           list packages grouped by the pkgbase they were built from\n"
+          "     --export          print every database entry as stored\n"
+          "     --lint            check the database for broken entries\n"
+          "     --check-signatures\n"
+          "                       verify every package signature in the database\n"
+          "     --verify          reread the databases after writing them\n"
+          "     --from-file=FILE  read the packages to operate on from FILE\n"
+          "     --checksums       write a sha256sum list of the repository\n"
           " -d, --drop            drop the specified package from the db\n"
+          "     --ignore-missing  only warn about packages to drop that aren't there\n"
           " -r, --root=PATH       set the root for the repository\n"
           " -p, --pool=PATH       set the pool to find packages in\n"
           " -m, --arch=ARCH       the architecture of the database\n"
           " -s, --sign            create a database signature\n"
+          "     --fingerprint=FPR sign with the key FPR\n"
           " -j, --bzip2           filter the archive through bzip2\n"
           " -J, --xz              filter the archive through xz\n"
           " -z, --gzip            filter the archive through gzip\n"
           " -Z, --compress        filter the archive through compress\n"
+          "     --zstd            filter the archive through zstd (default)\n"
+          "     --compression-level=LEVEL\n"
+          "                       compress the archive with the given level\n"
           "     --reflink         make repose make reflinks instead of symlinks\n"
-          "     --rebuild         force rebuild the repo\n", out);
+          "     --rebuild         force rebuild the repo\n"
+          "     --recompress      rewrite the databases with the current compression\n"
+          "     --vacuum          list package files replaced by a newer version\n"
+          "     --vacuum-delete   delete package files replaced by a newer version\n"
+          "     --strict          treat questionable package metadata as an error\n"
+          "     --skip-invalid    leave out invalid packages instead of failing\n"
+          "     --exclude=PATTERN ignore package files matching PATTERN\n"
+          "     --split-debug     keep debug packages in a separate database\n", out);
 
     exit(out == stderr ? EXIT_FAILURE : EXIT_SUCCESS);
 }
@@ -101,6 +157,72 @@ static _noreturn_ void elephant(void)
     exit(EXIT_SUCCESS);
 }
 
+static int parse_compression(const char *name)
+{
+    static const struct {
+        const char *name;
+        int filter;
+    } filters[] = {
+        { "none",     ARCHIVE_FILTER_NONE },
+        { "gzip",     ARCHIVE_FILTER_GZIP },
+        { "bzip2",    ARCHIVE_FILTER_BZIP2 },
+        { "xz",       ARCHIVE_FILTER_XZ },
+        { "compress", ARCHIVE_FILTER_COMPRESS },
+        { "zstd",     ARCHIVE_FILTER_ZSTD },
+    };
+
+    for (size_t i = 0; i < sizeof(filters) / sizeof(*filters); ++i) {
+        if (streq(name, filters[i].name))
+            return filters[i].filter;
+    }
+
+    errx(EXIT_FAILURE, "unknown compression %s, expected one of none, gzip, bzip2, xz, zstd or compress",
+         name);
+}
+
+static int parse_compression_level(const char *level)
+{
+    int value;
+    if (sscanf(level, "%d", &value) != 1 || value < 0)
+        errx(EXIT_FAILURE, "invalid compression level %s", level);
+    return value;
+}
+
+/* Defaults for the compression options, so CI can set them once for
+   every invocation. The command line still takes precedence. */
+static void compression_from_env(void)
+{
+    const char *env = getenv("REPOSE_COMPRESSION");
+    if (env && *env)
+        config.compression = parse_compression(env);
+
+    env = getenv("REPOSE_COMPRESSION_LEVEL");
+    if (env && *env)
+        config.compression_level = parse_compression_level(env);
+}
+
+static void check_compression_level(void)
+{
+    int min = 1, max = 9;
+
+    switch (config.compression) {
+    case ARCHIVE_FILTER_GZIP:
+    case ARCHIVE_FILTER_BZIP2:
+        break;
+    case ARCHIVE_FILTER_XZ:
+        min = 0;
+        break;
+    case ARCHIVE_FILTER_ZSTD:
+        max = 22;
+        break;
+    default:
+        errx(EXIT_FAILURE, "a compression level needs one of --bzip2, --xz, --zstd or --gzip");
+    }
+
+    if (config.compression_level < min || config.compression_level > max)
+        errx(EXIT_FAILURE, "compression level must be between %d and %d", min, max);
+}
+
 static int clone_file(const struct repo *repo, const char *filename)
 {
     _cleanup_close_ int src = openat(repo->poolfd, filename, O_RDONLY);
@@ -190,36 +312,427 @@ static void link_db(struct repo *repo)
         link_pkg(repo, node->data);
 }
 
-static void drop_from_repo(struct repo *repo, alpm_list_t *targets)
+static bool repo_has_target(const struct repo *repo, const char *target)
 {
-    if (!targets || !repo->cache)
-        return;
+    if (!repo || !repo->cache)
+        return false;
 
-    alpm_list_t *node;
+    const alpm_list_t *node;
     for (node = repo->cache->list; node; node = node->next) {
+        if (match_target_one(node->data, target))
+            return true;
+    }
+
+    return false;
+}
+
+/* Complain about targets that don't name anything in the database
+   before dropping anything, so a typo doesn't leave a partial result */
+static void check_drop_targets(const struct repo *repo, const struct repo *debugrepo,
+                               alpm_list_t *targets, bool ignore_missing)
+{
+    int missing = 0;
+
+    const alpm_list_t *node;
+    for (node = targets; node; node = node->next) {
+        const char *target = node->data;
+
+        if (repo_has_target(repo, target) || repo_has_target(debugrepo, target))
+            continue;
+
+        if (ignore_missing) {
+            warning("%s is not in %s, ignoring", target, repo->dbname);
+        } else {
+            warnx("%s is not in %s", target, repo->dbname);
+            ++missing;
+        }
+    }
+
+    if (missing)
+        errx(EXIT_FAILURE, "not dropping anything, %d target%s not found",
+             missing, missing == 1 ? "" : "s");
+}
+
+static inline const char *pkgbase(const struct pkg *pkg)
+{
+    return pkg->base ? pkg->base : pkg->name;
+}
+
+static bool repo_has_base(const struct repo *repo, const char *base)
+{
+    const alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        if (streq(pkgbase(node->data), base))
+            return true;
+    }
+
+    return false;
+}
+
+static void drop_package(struct repo *repo, struct pkg *pkg)
+{
+    trace("dropping %s\n", pkg->name);
+
+    repo->cache = pkgcache_remove(repo->cache, pkg, NULL);
+    unlink_pkg(repo, pkg);
+    package_free(pkg);
+    repo->dirty = true;
+}
+
+static void drop_from_repo(struct repo *repo, struct repo *debugrepo,
+                           alpm_list_t *targets, bool ignore_missing)
+{
+    check_drop_targets(repo, debugrepo, targets, ignore_missing);
+
+    if (!targets)
+        return;
+
+    /* Dropping a package frees its node, so hold on to the next one */
+    alpm_list_t *node, *next;
+    if (debugrepo && debugrepo->cache) {
+        for (node = debugrepo->cache->list; node; node = next) {
+            struct pkg *pkg = node->data;
+            next = node->next;
+
+            if (match_targets(pkg, targets))
+                drop_package(debugrepo, pkg);
+        }
+    }
+
+    if (!repo->cache)
+        return;
+
+    for (node = repo->cache->list; node; node = next) {
         struct pkg *pkg = node->data;
+        next = node->next;
 
-        if (match_targets(pkg, targets)) {
-            trace("dropping %s\n", pkg->name);
+        if (!match_targets(pkg, targets))
+            continue;
 
-            repo->cache = pkgcache_remove(repo->cache, pkg, NULL);
-            unlink_pkg(repo, pkg);
-            package_free(pkg);
-            repo->dirty = true;
+        _cleanup_free_ char *base = strdup(pkgbase(pkg));
+        drop_package(repo, pkg);
+
+        /* makepkg builds one debug package per pkgbase, so it only goes
+           once nothing built from that base is left */
+        if (debugrepo && debugrepo->cache && !repo_has_base(repo, base)) {
+            _cleanup_free_ char *debugname = joinstring(base, "-debug", NULL);
+            struct pkg *debugpkg = pkgcache_find(debugrepo->cache, debugname);
+            if (debugpkg)
+                drop_package(debugrepo, debugpkg);
         }
     }
 }
 
-static void list_repo(struct repo *repo)
+/* List the repository, or with a cutoff only the packages built before it */
+static void list_repo(struct repo *repo, time_t cutoff)
 {
     alpm_list_t *node;
     for (node = repo->cache->list; node; node = node->next) {
         struct pkg *pkg = node->data;
 
+        if (cutoff && pkg->builddate >= cutoff)
+            continue;
+
         printf("%s %s\n", pkg->name, pkg->version);
     }
 }
 
+static void print_date(const char *label, time_t date)
+{
+    char buf[64];
+    strftime(buf, sizeof(buf), "%F %T %Z", gmtime(&date));
+    printf("%-16s%s\n", label, buf);
+}
+
+static void stats_repo(struct repo *repo)
+{
+    size_t count = 0, size = 0, isize = 0;
+    time_t newest = 0, oldest = 0;
+    alpm_list_t *arches = NULL;
+
+    alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        struct pkg *pkg = node->data;
+
+        size += pkg->size;
+        isize += pkg->isize;
+
+        if (pkg->arch && !alpm_list_find_str(arches, pkg->arch))
+            arches = alpm_list_add(arches, pkg->arch);
+
+        if (!count || pkg->builddate > newest)
+            newest = pkg->builddate;
+        if (!count || pkg->builddate < oldest)
+            oldest = pkg->builddate;
+
+        ++count;
+    }
+
+    printf("%-16s%zu\n", "packages:", count);
+    printf("%-16s%zu\n", "download size:", size);
+    printf("%-16s%zu\n", "installed size:", isize);
+
+    printf("%-16s", "architectures:");
+    for (node = arches; node; node = node->next)
+        printf("%s%s", (const char *)node->data, node->next ? " " : "");
+    putchar('\n');
+
+    if (count) {
+        print_date("newest build:", newest);
+        print_date("oldest build:", oldest);
+    }
+
+    alpm_list_free(arches);
+}
+
+static void diff_repo(struct repo *repo, const char *path)
+{
+    _cleanup_close_ int fd = open(path, O_RDONLY);
+    check_posix(fd, "failed to open database %s", path);
+
+    struct pkgcache *old = pkgcache_create(100);
+    check_posix(load_database(fd, &old), "failed to read database %s", path);
+
+    alpm_list_t *node;
+    for (node = old->list; node; node = node->next) {
+        struct pkg *pkg = node->data;
+        struct pkg *new = pkgcache_find(repo->cache, pkg->name);
+
+        if (!new) {
+            printf("removed %s %s\n", pkg->name, pkg->version);
+            continue;
+        }
+
+        switch (alpm_pkg_vercmp(new->version, pkg->version)) {
+        case 1:
+            printf("upgraded %s %s -> %s\n", pkg->name, pkg->version, new->version);
+            break;
+        case -1:
+            printf("downgraded %s %s -> %s\n", pkg->name, pkg->version, new->version);
+            break;
+        }
+    }
+
+    for (node = repo->cache->list; node; node = node->next) {
+        struct pkg *pkg = node->data;
+
+        if (!pkgcache_find(old, pkg->name))
+            printf("added %s %s\n", pkg->name, pkg->version);
+    }
+
+    for (node = old->list; node; node = node->next)
+        package_free(node->data);
+    pkgcache_free(old);
+}
+
+static void _printf_(3, 4) lint_problem(int *problems, const struct pkg *pkg,
+                                         const char *fmt, ...)
+{
+    va_list ap;
+
+    printf("%s: ", pkg->name);
+    va_start(ap, fmt);
+    vprintf(fmt, ap);
+    va_end(ap);
+    putchar('\n');
+
+    ++*problems;
+}
+
+/* Look for entries pacman would choke on, returning how many we found */
+static int lint_repo(struct repo *repo)
+{
+    int problems = 0;
+
+    alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        const struct pkg *pkg = node->data;
+
+        const size_t entries = alpm_list_count(pkg->entries);
+        if (entries > 1)
+            lint_problem(&problems, pkg, "listed %zu times", entries);
+
+        /* Entries are filed under the directory name, %NAME% came later,
+           so looking the package up by its name only finds it if the two
+           agree */
+        const struct pkg *named = pkgcache_find(repo->cache, pkg->name);
+        if (named && named != pkg)
+            lint_problem(&problems, pkg, "duplicate entry, also listed under its own name");
+        else if (!named)
+            lint_problem(&problems, pkg, "directory name doesn't match %%NAME%%");
+        else if (entries == 1 && !package_entry_matches(pkg, pkg->entries->data)) {
+            lint_problem(&problems, pkg, "directory %s doesn't match %%NAME%%-%%VERSION%%",
+                         (const char *)pkg->entries->data);
+        }
+
+        if (!pkg->filename)
+            lint_problem(&problems, pkg, "missing %%FILENAME%%");
+        if (package_validate(pkg) & PKG_INVALID_VERSION)
+            lint_problem(&problems, pkg, "invalid %%VERSION%% %s", pkg->version);
+        if (!pkg->sha256sum && !pkg->base64sig)
+            lint_problem(&problems, pkg, "missing both %%SHA256SUM%% and %%PGPSIG%%");
+        if (!pkg->arch)
+            lint_problem(&problems, pkg, "missing %%ARCH%%");
+    }
+
+    return problems;
+}
+
+/* List everything in the repository that needs name to run or build */
+static void required_by(struct repo *repo, const char *name)
+{
+    static const struct {
+        enum pkg_entry kind;
+        const char *label;
+    } kinds[] = {
+        { PKG_DEPENDS,      "depends" },
+        { PKG_MAKEDEPENDS,  "makedepends" },
+        { PKG_CHECKDEPENDS, "checkdepends" },
+    };
+
+    const struct pkg *target = pkgcache_find(repo->cache, name);
+
+    alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        const struct pkg *pkg = node->data;
+
+        for (size_t i = 0; i < sizeof(kinds) / sizeof(*kinds); ++i) {
+            /* If the package is in the repository, honour version
+               constraints and what it provides */
+            const bool needed = target ? package_requires(pkg, kinds[i].kind, target)
+                                       : package_depends_on(pkg, kinds[i].kind, name);
+            if (needed)
+                printf("%s %s\n", pkg->name, kinds[i].label);
+        }
+    }
+}
+
+/* Check every %PGPSIG% in the database against its package in the pool,
+   returning how many failed */
+static int check_signatures(struct repo *repo)
+{
+    int problems = 0;
+
+    alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        const struct pkg *pkg = node->data;
+        if (!pkg->base64sig)
+            continue;
+
+        size_t len;
+        _cleanup_free_ char *signature = base64_decode((const unsigned char *)pkg->base64sig,
+                                                       strlen(pkg->base64sig), &len);
+        if (!signature) {
+            lint_problem(&problems, pkg, "%%PGPSIG%% isn't valid base64");
+            continue;
+        }
+
+        if (faccessat(repo->poolfd, pkg->filename, F_OK, 0) < 0) {
+            lint_problem(&problems, pkg, "%s is missing from the pool", pkg->filename);
+        } else if (gpgme_verify_signature(repo->poolfd, pkg->filename, signature, len) < 0) {
+            lint_problem(&problems, pkg, "bad signature for %s", pkg->filename);
+        } else {
+            trace("%s: good signature\n", pkg->name);
+        }
+    }
+
+    return problems;
+}
+
+static int base_cmp(const void *p1, const void *p2)
+{
+    const struct pkg *pkg1 = p1;
+    const struct pkg *pkg2 = p2;
+
+    int cmp = strcmp(pkgbase(pkg1), pkgbase(pkg2));
+    if (cmp == 0)
+        cmp = strcmp(pkg1->name, pkg2->name);
+    return cmp;
+}
+
+/* Print each pkgbase with the version it was built at and the packages
+   built from it, so a split package can be handled as a unit */
+static void list_bases(struct repo *repo)
+{
+    alpm_list_t *sorted = alpm_list_msort(alpm_list_copy(repo->cache->list),
+                                          alpm_list_count(repo->cache->list), base_cmp);
+
+    const struct pkg *prev = NULL;
+    alpm_list_t *node;
+    for (node = sorted; node; node = node->next) {
+        const struct pkg *pkg = node->data;
+
+        if (!prev || !streq(pkgbase(prev), pkgbase(pkg))) {
+            if (prev)
+                putchar('\n');
+            printf("%s %s", pkgbase(pkg), pkg->version);
+        }
+
+        printf(" %s", pkg->name);
+        prev = pkg;
+    }
+
+    if (prev)
+        putchar('\n');
+    alpm_list_free(sorted);
+}
+
+/* Print every entry the way the database stores it, desc then depends */
+static void export_repo(struct repo *repo)
+{
+    struct buffer buf = {0};
+
+    alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        const struct pkg *pkg = node->data;
+
+        buffer_clear(&buf);
+        write_desc(&buf, pkg);
+        write_depends(&buf, pkg);
+        fwrite(buf.data, 1, buf.len, stdout);
+    }
+
+    buffer_release(&buf);
+}
+
+/* The read-only operations requested on the command line */
+struct query {
+    bool list, stats, export, bases, lint, signatures;
+    time_t cutoff;
+    const char *diff;
+    const char *required;
+};
+
+static inline bool query_requested(const struct query *query)
+{
+    return query->list || query->stats || query->diff || query->required ||
+           query->export || query->bases || query->lint || query->signatures;
+}
+
+static int query_repo(struct repo *repo, const struct query *query)
+{
+    if (query->list)
+        list_repo(repo, query->cutoff);
+    if (query->bases)
+        list_bases(repo);
+    if (query->export)
+        export_repo(repo);
+    if (query->stats)
+        stats_repo(repo);
+    if (query->diff)
+        diff_repo(repo, query->diff);
+    if (query->required)
+        required_by(repo, query->required);
+
+    int problems = 0;
+    if (query->lint)
+        problems += lint_repo(repo);
+    if (query->signatures)
+        problems += check_signatures(repo);
+
+    return problems ? EXIT_FAILURE : EXIT_SUCCESS;
+}
+
 static void reduce_repo(struct repo *repo)
 {
     if (!repo->cache)
@@ -291,6 +804,136 @@ static void update_repo(struct repo *repo, struct pkgcache *src)
     }
 }
 
+/* Move every debug package out of cache and into a new cache of its own */
+static struct pkgcache *split_debug(struct pkgcache **cache)
+{
+    struct pkgcache *debug = pkgcache_create(100);
+    if (!*cache)
+        return debug;
+
+    alpm_list_t *node, *next;
+    for (node = (*cache)->list; node; node = next) {
+        struct pkg *pkg = node->data;
+        next = node->next;
+
+        if (package_is_debug(pkg)) {
+            *cache = pkgcache_remove(*cache, pkg, NULL);
+            debug = pkgcache_add(debug, pkg);
+        }
+    }
+
+    return debug;
+}
+
+static void drop_debug(struct repo *repo)
+{
+    struct pkgcache *debug = split_debug(&repo->cache);
+
+    alpm_list_t *node;
+    for (node = debug->list; node; node = node->next) {
+        struct pkg *pkg = node->data;
+
+        trace("moving %s to the debug database\n", pkg->name);
+        package_free(pkg);
+        repo->dirty = true;
+    }
+
+    pkgcache_free(debug);
+}
+
+static inline bool same_field(const char *s1, const char *s2)
+{
+    if (!s1 || !s2)
+        return s1 == s2;
+    return streq(s1, s2);
+}
+
+static void reread_db(struct repo *repo, const char *filename, struct pkgcache **cache)
+{
+    _cleanup_free_ char *tmpname = database_tmpname(filename);
+    _cleanup_close_ int dbfd = openat(repo->rootfd, tmpname, O_RDONLY);
+    check_posix(dbfd, "failed to reopen %s database", filename);
+    check_posix(load_database(dbfd, cache), "failed to reread %s database", filename);
+}
+
+/* Read back what we just wrote and make sure it describes the same
+   packages we have in memory. This runs before the databases are signed
+   and moved into place, so a bad write never replaces a good one. */
+static void verify_repo(struct repo *repo)
+{
+    struct pkgcache *cache = pkgcache_create(repo->cache->entries);
+
+    reread_db(repo, repo->dbname, &cache);
+    if (repo->filesname)
+        reread_db(repo, repo->filesname, &cache);
+
+    if (cache->entries != repo->cache->entries) {
+        errx(EXIT_FAILURE, "verification failed: %s holds %zu packages, expected %zu",
+             repo->dbname, cache->entries, repo->cache->entries);
+    }
+
+    alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        const struct pkg *pkg = node->data;
+        const struct pkg *reread = pkgcache_find(cache, pkg->name);
+
+        if (!reread)
+            errx(EXIT_FAILURE, "verification failed: %s is missing from %s", pkg->name, repo->dbname);
+
+        if (!same_field(reread->version, pkg->version) ||
+            !same_field(reread->filename, pkg->filename) ||
+            !same_field(reread->base64sig, pkg->base64sig) ||
+            (!pkg->base64sig && !same_field(reread->sha256sum, pkg->sha256sum))) {
+            errx(EXIT_FAILURE, "verification failed: %s doesn't match what was written to %s",
+                 pkg->name, repo->dbname);
+        }
+
+        if (repo->filesname && alpm_list_count(reread->files) != alpm_list_count(pkg->files)) {
+            errx(EXIT_FAILURE, "verification failed: file list for %s doesn't match what was written to %s",
+                 pkg->name, repo->filesname);
+        }
+    }
+
+    for (node = cache->list; node; node = node->next)
+        package_free(node->data);
+    pkgcache_free(cache);
+
+    trace("verified %s\n", repo->dbname);
+}
+
+static void commit_repo(struct repo *repo)
+{
+    if (!repo->dirty) {
+        trace("%s does not need updating\n", repo->dbname);
+        return;
+    }
+
+    struct write_summary db = {0}, files = {0};
+
+    write_database(repo, repo->dbname, DB_DESC | DB_DEPENDS, &db);
+
+    if (repo->filesname) {
+        write_database(repo, repo->filesname, DB_FILES, &files);
+    }
+
+    if (config.verify)
+        verify_repo(repo);
+
+    publish_database(repo, repo->dbname);
+    if (repo->filesname)
+        publish_database(repo, repo->filesname);
+
+    if (config.checksums) {
+        _cleanup_free_ char *checksums = joinstring(repo->dbname, ".sha256", NULL);
+        write_checksums(repo, checksums, &db, &files);
+    }
+
+    free(db.sha256sum);
+    free(files.sha256sum);
+
+    link_db(repo);
+}
+
 static alpm_list_t *parse_targets(char *targets[], int count)
 {
     int i;
@@ -310,7 +953,7 @@ static int load_db(struct repo *repo, const char *filename)
     }
 
     if (load_database(dbfd, &repo->cache) < 0) {
-        warn("failed to open %s database", filename);
+        warning("failed to open %s database: %s", filename, strerror(errno));
         return -1;
     }
 
@@ -333,6 +976,71 @@ static void check_signature(struct repo *repo, const char *name)
     }
 }
 
+static bool repo_has_file(const struct repo *repo, const char *filename)
+{
+    if (!repo || !repo->cache)
+        return false;
+
+    /* makepkg names packages name-pkgver-pkgrel-arch.pkg.tar[.ext], so
+       try the name that implies first */
+    const char *end = strstr(filename, ".pkg.tar");
+    for (int i = 0; end && i < 3; ++i)
+        end = memrchr(filename, '-', end - filename);
+
+    if (end) {
+        _cleanup_free_ char *name = strndup(filename, end - filename);
+        const struct pkg *pkg = pkgcache_find(repo->cache, name);
+        if (pkg && streq(pkg->filename, filename))
+            return true;
+    }
+
+    const alpm_list_t *node;
+    for (node = repo->cache->list; node; node = node->next) {
+        const struct pkg *pkg = node->data;
+        if (streq(pkg->filename, filename))
+            return true;
+    }
+
+    return false;
+}
+
+static void vacuum_file(const struct repo *repo, const char *filename, bool delete)
+{
+    if (faccessat(repo->poolfd, filename, F_OK, 0) < 0) {
+        if (errno != ENOENT)
+            err(EXIT_FAILURE, "couldn't access %s", filename);
+        return;
+    }
+
+    if (!delete) {
+        printf("%s\n", filename);
+        return;
+    }
+
+    trace("removing %s\n", filename);
+    check_posix(unlinkat(repo->poolfd, filename, 0), "failed to remove %s", filename);
+}
+
+/* Deal with the package files, and their signatures, that the pool scan
+   passed over for a newer version of the same package. Unless asked to
+   delete them, just print them. Anything a database still refers to is
+   left alone. */
+static void vacuum_pool(const struct repo *repo, const struct repo *debugrepo,
+                        const alpm_list_t *superseded, bool delete)
+{
+    const alpm_list_t *node;
+    for (node = superseded; node; node = node->next) {
+        const char *filename = node->data;
+
+        if (repo_has_file(repo, filename) || repo_has_file(debugrepo, filename))
+            continue;
+
+        _cleanup_free_ char *signame = joinstring(filename, ".sig", NULL);
+        vacuum_file(repo, filename, delete);
+        vacuum_file(repo, signame, delete);
+    }
+}
+
 static int init_repo(struct repo *repo, const char *reponame, bool files,
                      bool load_cache)
 {
@@ -381,31 +1089,63 @@ static int init_repo(struct repo *repo, const char *reponame, bool files,
     return 0;
 }
 
-static alpm_list_t *load_manifest(struct repo *repo, const char *reponame)
+/* Read one target per line, ignoring blank lines and '#' comments */
+static alpm_list_t *read_targets(alpm_list_t *list, FILE *fp, const char *filename)
 {
-    _cleanup_free_ char *manifest = joinstring(reponame, ".manifest", NULL);
-    _cleanup_fclose_ FILE *fp = fopenat(repo->rootfd, manifest, "r");
-    if (fp == NULL)
-        return NULL;
-
-    alpm_list_t *list = NULL;
     for (;;) {
         errno = 0;
-        char *line = NULL;
+        _cleanup_free_ char *line = NULL;
         ssize_t nbytes_r = getline(&line, &(size_t){ 0 }, fp);
         if (nbytes_r < 0) {
             if (errno != 0)
-                err(EXIT_FAILURE, "Failed to read manifest file");
+                err(EXIT_FAILURE, "failed to read %s", filename);
             break;
         }
 
-        line[nbytes_r - 1] = 0;
-        if (line[0])
-            list = alpm_list_add(list, line);
+        char *comment = strchr(line, '#');
+        if (comment)
+            *comment = 0;
+
+        char *target = strstrip(line);
+        if (!target[0])
+            continue;
+
+        /* Packages are always looked up in the pool, so a path to one
+           is just as good as its filename */
+        char *sep = strrchr(target, '/');
+        if (sep) {
+            if (!sep[1])
+                errx(EXIT_FAILURE, "%s: %s is not a package", filename, target);
+            target = sep + 1;
+        }
+
+        list = alpm_list_add(list, strdup(target));
     }
     return list;
 }
 
+static alpm_list_t *load_manifest(struct repo *repo, const char *reponame)
+{
+    _cleanup_free_ char *manifest = joinstring(reponame, ".manifest", NULL);
+    _cleanup_fclose_ FILE *fp = fopenat(repo->rootfd, manifest, "r");
+    if (fp == NULL)
+        return NULL;
+
+    return read_targets(NULL, fp, manifest);
+}
+
+static alpm_list_t *load_targets_file(alpm_list_t *targets, const char *filename)
+{
+    if (streq(filename, "-"))
+        return read_targets(targets, stdin, "stdin");
+
+    _cleanup_fclose_ FILE *fp = fopen(filename, "r");
+    if (fp == NULL)
+        err(EXIT_FAILURE, "failed to open %s", filename);
+
+    return read_targets(targets, fp, filename);
+}
+
 static char *get_rootname(char *name)
 {
     char *sep = strrchr(name, '.');
@@ -417,7 +1157,10 @@ static char *get_rootname(char *name)
 int main(int argc, char *argv[])
 {
     const char *rootname;
-    bool files = false, rebuild = false, drop = false, list = false;
+    bool files = false, rebuild = false, drop = false, debug = false;
+    bool recompress = false, vacuum = false, vacuum_delete = false, ignore_missing = false;
+    const char *from_file = NULL;
+    struct query query = {0};
 
     setlocale(LC_ALL, "");
 
@@ -427,6 +1170,7 @@ int main(int argc, char *argv[])
         { "drop",     no_argument,       0, 'd' },
         { "list",     no_argument,       0, 'l' },
         { "verbose",  no_argument,       0, 'v' },
+        { "quiet",    no_argument,       0, 'q' },
         { "files",    no_argument,       0, 'f' },
         { "sign",     no_argument,       0, 's' },
         { "root",     required_argument, 0, 'r' },
@@ -439,13 +1183,37 @@ int main(int argc, char *argv[])
         { "reflink",  no_argument,       0, 0x100 },
         { "rebuild",  no_argument,       0, 0x101 },
         { "elephant", no_argument,       0, 0x102 },
+        { "strict",   no_argument,       0, 0x103 },
+        { "stats",    no_argument,       0, 0x104 },
+        { "exclude",  required_argument, 0, 0x105 },
+        { "split-debug", no_argument,    0, 0x106 },
+        { "compression-level", required_argument, 0, 0x107 },
+        { "fingerprint", required_argument, 0, 0x108 },
+        { "diff",     required_argument, 0, 0x109 },
+        { "verify",   no_argument,       0, 0x10a },
+        { "from-file", required_argument, 0, 0x10b },
+        { "checksums", no_argument,      0, 0x10c },
+        { "older-than", required_argument, 0, 0x10d },
+        { "recompress", no_argument,     0, 0x10e },
+        { "lint",     no_argument,       0, 0x10f },
+        { "check-signatures", no_argument, 0, 0x110 },
+        { "required-by", required_argument, 0, 0x111 },
+        { "vacuum",   no_argument,       0, 0x112 },
+        { "export",   no_argument,       0, 0x113 },
+        { "skip-invalid", no_argument,   0, 0x114 },
+        { "ignore-missing", no_argument, 0, 0x115 },
+        { "bases",    no_argument,       0, 0x116 },
+        { "vacuum-delete", no_argument,  0, 0x117 },
+        { "zstd",     no_argument,       0, 0x118 },
         { 0, 0, 0, 0 }
     };
 
     struct repo repo = { .root = "." };
 
+    compression_from_env();
+
     for (;;) {
-        int opt = getopt_long(argc, argv, "hVvdlfsr:p:m:jJzZ", opts, NULL);
+        int opt = getopt_long(argc, argv, "hVvqdlfsr:p:m:jJzZ", opts, NULL);
         if (opt < 0)
             break;
 
@@ -459,11 +1227,14 @@ int main(int argc, char *argv[])
         case 'v':
             config.verbose += 1;
             break;
+        case 'q':
+            config.quiet = true;
+            break;
         case 'd':
             drop = true;
             break;
         case 'l':
-            list = true;
+            query.list = true;
             break;
         case 'f':
             files = true;
@@ -502,6 +1273,80 @@ int main(int argc, char *argv[])
         case 0x102:
             elephant();
             break;
+        case 0x103:
+            config.strict = true;
+            break;
+        case 0x104:
+            query.stats = true;
+            break;
+        case 0x105:
+            config.excludes = alpm_list_add(config.excludes, optarg);
+            break;
+        case 0x106:
+            debug = true;
+            break;
+        case 0x107:
+            config.compression_level = parse_compression_level(optarg);
+            break;
+        case 0x108:
+            config.fingerprint = optarg;
+            break;
+        case 0x109:
+            query.diff = optarg;
+            break;
+        case 0x10a:
+            config.verify = true;
+            break;
+        case 0x10b:
+            from_file = optarg;
+            break;
+        case 0x10c:
+            config.checksums = true;
+            break;
+        case 0x10d: {
+            unsigned days;
+            if (sscanf(optarg, "%u", &days) != 1)
+                errx(EXIT_FAILURE, "invalid number of days %s", optarg);
+            query.cutoff = time(NULL) - (time_t)days * 24 * 60 * 60;
+            query.list = true;
+            break;
+        }
+        case 0x10e:
+            /* Everything in the database is already known, it only
+               needs writing out again */
+            recompress = true;
+            repo.dirty = true;
+            break;
+        case 0x10f:
+            query.lint = true;
+            break;
+        case 0x110:
+            query.signatures = true;
+            break;
+        case 0x111:
+            query.required = optarg;
+            break;
+        case 0x112:
+            vacuum = true;
+            break;
+        case 0x113:
+            query.export = true;
+            break;
+        case 0x114:
+            config.skip_invalid = true;
+            break;
+        case 0x115:
+            ignore_missing = true;
+            break;
+        case 0x116:
+            query.bases = true;
+            break;
+        case 0x117:
+            vacuum = vacuum_delete = true;
+            break;
+        case 0x118:
+            config.compression = ARCHIVE_FILTER_ZSTD;
+            break;
         }
     }
 
@@ -511,54 +1356,117 @@ int main(int argc, char *argv[])
     if (argc == 0)
         errx(1, "incorrect number of arguments provided");
 
+    if (config.compression_level >= 0)
+        check_compression_level();
+
+    if (config.quiet)
+        config.verbose = 0;
+
     if (!config.arch) {
         struct utsname uts;
         uname(&uts);
         config.arch = strdup(uts.machine);
     }
 
-    if (list && drop)
+    const bool querying = query_requested(&query);
+
+    if (querying && drop)
         errx(EXIT_FAILURE, "List and drop operations are mutually exclusive");
 
-    if (rebuild && (list || drop)) {
-        fprintf(stderr, "Can't rebuild while performing a list or drop operation.\n"
-                        "Ignoring the --rebuild flag.\n");
+    if (rebuild && (querying || drop)) {
+        warning("can't rebuild while performing a list or drop operation, ignoring --rebuild");
         rebuild = false;
     }
 
+    if (streq(*argv, "-")) {
+        /* libarchive is happy to stream a database from a pipe, so
+           there's no need to buffer it to disk first */
+        if (!querying)
+            errx(EXIT_FAILURE, "A database can only be read from stdin with --list, --stats, --diff, "
+                 "--lint, --required-by, --export or --bases");
+        if (query.signatures)
+            errx(EXIT_FAILURE, "Checking signatures needs the pool, so the database can't come from stdin");
+
+        repo.cache = pkgcache_create(100);
+        check_posix(load_database(STDIN_FILENO, &repo.cache),
+                    "failed to read database from stdin");
+        return query_repo(&repo, &query);
+    }
+
     rootname = get_rootname(*argv++), --argc;
+    if (strchr(rootname, '/'))
+        errx(EXIT_FAILURE, "database name %s can't contain a path, use --root to place it",
+             rootname);
+
     int ret = init_repo(&repo, rootname, files, !rebuild);
-    if (list) {
+    if (querying) {
         check_posix(ret, "failed to open database %s.db", rootname);
-        list_repo(&repo);
-        return 0;
+        return query_repo(&repo, &query);
     }
 
+    if (config.sign)
+        gpgme_check_signing_key(config.fingerprint);
+
     alpm_list_t *targets = parse_targets(argv, argc);
+    if (from_file) {
+        targets = load_targets_file(targets, from_file);
+        if (!targets)
+            errx(EXIT_FAILURE, "no packages listed in %s", from_file);
+    }
+
+    alpm_list_t *superseded = NULL;
+    _cleanup_free_ char *debugname = joinstring(rootname, "-debug", NULL);
+    struct repo debugrepo = {
+        .root = repo.root,
+        .pool = repo.pool,
+        .dirty = rebuild || recompress
+    };
 
     if (drop) {
-        drop_from_repo(&repo, targets);
+        /* There's nothing to drop from a debug database that doesn't
+           exist, and no reason to create an empty one */
+        if (debug && init_repo(&debugrepo, debugname, files, true) < 0)
+            debug = false;
+
+        drop_from_repo(&repo, debug ? &debugrepo : NULL, targets, ignore_missing);
+        if (debug)
+            commit_repo(&debugrepo);
+    } else if (recompress && !rebuild && !targets) {
+        /* Nothing about the packages is changing, the databases just
+           need writing out again as they are */
+        if (faccessat(repo.rootfd, repo.dbname, F_OK, 0) < 0)
+            err(EXIT_FAILURE, "nothing to recompress, couldn't access %s", repo.dbname);
+        if (debug && init_repo(&debugrepo, debugname, files, true) == 0)
+            commit_repo(&debugrepo);
     } else {
-        if (argc == 0) {
+        if (argc == 0 && !from_file) {
             targets = load_manifest(&repo, rootname);
         }
 
-        struct pkgcache *filecache = get_filecache(repo.poolfd, targets, config.arch);
+        struct pkgcache *filecache = get_filecache(repo.poolfd, targets, config.excludes,
+                                                    config.arch, vacuum ? &superseded : NULL);
         check_null(filecache, "failed to get filecache");
 
+        if (debug) {
+            init_repo(&debugrepo, debugname, files, !rebuild);
+            drop_debug(&repo);
+
+            struct pkgcache *debugcache = split_debug(&filecache);
+            reduce_repo(&debugrepo);
+            update_repo(&debugrepo, debugcache);
+            commit_repo(&debugrepo);
+            pkgcache_free(debugcache);
+        }
+
         reduce_repo(&repo);
         update_repo(&repo, filecache);
     }
 
-    if (!repo.dirty) {
-        trace("repo does not need updating\n");
-    } else {
-        write_database(&repo, repo.dbname, DB_DESC | DB_DEPENDS);
-
-        if (repo.filesname) {
-            write_database(&repo, repo.filesname, DB_FILES);
-        }
+    commit_repo(&repo);
 
-        link_db(&repo);
+    if (vacuum) {
+        vacuum_pool(&repo, debug ? &debugrepo : NULL, superseded, vacuum_delete);
+        alpm_list_free_inner(superseded, free);
+        alpm_list_free(superseded);
     }
 }
@@ -19,11 +19,21 @@ struct repo {
 
 struct config {
     int verbose;
+    bool quiet;
     int compression;
+    int compression_level;
     bool reflink;
     bool sign;
+    bool strict;
+    bool verify;
+    bool checksums;
+    bool skip_invalid;
     char *arch;
+    char *fingerprint;
+    alpm_list_t *excludes;
 };
 
 extern struct config config;
 void trace(const char *fmt, ...) _printf_(1, 2);
+void debug(const char *fmt, ...) _printf_(1, 2);
+void warning(const char *fmt, ...) _printf_(1, 2);
@@ -4,6 +4,7 @@
 #include <stdio.h>
 #include <stdbool.h>
 #include <stdarg.h>
+#include <ctype.h>
 #include <fcntl.h>
 #include <unistd.h>
 #include <locale.h>
@@ -36,6 +37,23 @@ static inline char *sig_for(const char *file)
     return joinstring(file, ".sig", NULL);
 }
 
+/* Compare two fingerprints, ignoring case and any spaces used to group
+ * the digits */
+static bool fingerprint_eq(const char *fpr1, const char *fpr2)
+{
+    for (;; ++fpr1, ++fpr2) {
+        while (*fpr1 == ' ')
+            ++fpr1;
+        while (*fpr2 == ' ')
+            ++fpr2;
+
+        if (tolower(*fpr1) != tolower(*fpr2))
+            return false;
+        if (!*fpr1)
+            return true;
+    }
+}
+
 static int init_gpgme(void)
 {
     static int inited = false;
@@ -67,34 +85,19 @@ static int init_gpgme(void)
     return 0;
 }
 
-int gpgme_verify(int rootfd, const char *file)
+/* Check a detached signature, taking ownership of both data objects */
+static int verify_data(gpgme_data_t in, gpgme_data_t sig)
 {
     gpgme_error_t err;
     gpgme_ctx_t ctx;
-    gpgme_data_t in, sig;
     gpgme_verify_result_t result;
     gpgme_signature_t sigs;
     int rc = 0;
 
-    if (init_gpgme() < 0)
-        return -1;
-
-    _cleanup_free_ char *sigfile = sig_for(file);
-    _cleanup_close_ int sigfd = openat(rootfd, sigfile, O_RDONLY);
-    _cleanup_close_ int fd = openat(rootfd, file, O_RDONLY);
-
     err = gpgme_new(&ctx);
     if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
         gpgme_err(EXIT_FAILURE, err, "failed to call gpgme_new()");
 
-    err = gpgme_data_new_from_fd(&in, fd);
-    if (err)
-        gpgme_err(EXIT_FAILURE, err, "error reading %s", file);
-
-    err = gpgme_data_new_from_fd(&sig, sigfd);
-    if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
-        gpgme_err(EXIT_FAILURE, err, "error reading %s", sigfile);
-
     err = gpgme_op_verify(ctx, sig, in, NULL);
     if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
         gpgme_err(EXIT_FAILURE, err, "failed to verify");
@@ -127,23 +130,138 @@ int gpgme_verify(int rootfd, const char *file)
     return rc;
 }
 
+int gpgme_verify(int rootfd, const char *file)
+{
+    gpgme_error_t err;
+    gpgme_data_t in, sig;
+
+    if (init_gpgme() < 0)
+        return -1;
+
+    _cleanup_free_ char *sigfile = sig_for(file);
+    _cleanup_close_ int sigfd = openat(rootfd, sigfile, O_RDONLY);
+    _cleanup_close_ int fd = openat(rootfd, file, O_RDONLY);
+
+    err = gpgme_data_new_from_fd(&in, fd);
+    if (err)
+        gpgme_err(EXIT_FAILURE, err, "error reading %s", file);
+
+    err = gpgme_data_new_from_fd(&sig, sigfd);
+    if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
+        gpgme_err(EXIT_FAILURE, err, "error reading %s", sigfile);
+
+    return verify_data(in, sig);
+}
+
+/* Like gpgme_verify, but with the signature already in memory, such as
+ * one decoded from a database's %PGPSIG% */
+int gpgme_verify_signature(int dirfd, const char *file, const char *signature, size_t len)
+{
+    gpgme_error_t err;
+    gpgme_data_t in, sig;
+
+    if (init_gpgme() < 0)
+        return -1;
+
+    _cleanup_close_ int fd = openat(dirfd, file, O_RDONLY);
+    if (fd < 0)
+        return -1;
+
+    err = gpgme_data_new_from_fd(&in, fd);
+    if (err)
+        gpgme_err(EXIT_FAILURE, err, "error reading %s", file);
+
+    err = gpgme_data_new_from_mem(&sig, signature, len, 1);
+    if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
+        gpgme_err(EXIT_FAILURE, err, "error reading signature for %s", file);
+
+    return verify_data(in, sig);
+}
+
+/* The key as a whole has to be usable, and so does at least one of its
+   subkeys that's capable of signing, since that's what gpg will use */
+static bool key_can_sign(gpgme_key_t key)
+{
+    if (key->revoked || key->expired || key->disabled)
+        return false;
+
+    gpgme_subkey_t subkey;
+    for (subkey = key->subkeys; subkey; subkey = subkey->next) {
+        if (subkey->can_sign && !subkey->revoked && !subkey->expired &&
+            !subkey->disabled && !subkey->invalid)
+            return true;
+    }
+
+    return false;
+}
+
+/* Make sure there's a secret key gpg can sign with, so a misconfigured
+   keyring is caught before the databases are built rather than after */
+void gpgme_check_signing_key(const char *fingerprint)
+{
+    gpgme_error_t err;
+    gpgme_ctx_t ctx;
+    gpgme_key_t key;
+    bool found = false;
+
+    if (init_gpgme() < 0)
+        errx(EXIT_FAILURE, "signing requested, but gpg doesn't support OpenPGP");
+
+    err = gpgme_new(&ctx);
+    if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
+        gpgme_err(EXIT_FAILURE, err, "failed to call gpgme_new()");
+
+    /* Without a fingerprint gpg falls back on its default key, which is
+       the first usable secret key unless gpg.conf says otherwise */
+    err = gpgme_op_keylist_start(ctx, fingerprint, 1);
+    if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
+        gpgme_err(EXIT_FAILURE, err, "failed to list secret keys");
+
+    while (!found && gpgme_op_keylist_next(ctx, &key) == GPG_ERR_NO_ERROR) {
+        found = key_can_sign(key);
+        gpgme_key_unref(key);
+    }
+
+    gpgme_op_keylist_end(ctx);
+    gpgme_release(ctx);
+
+    if (!found) {
+        if (fingerprint)
+            errx(EXIT_FAILURE, "signing requested, but there's no usable secret key for %s",
+                 fingerprint);
+        errx(EXIT_FAILURE, "signing requested, but there's no usable secret key");
+    }
+}
+
+/* gpg reports the subkey that made a signature, which is rarely the
+   primary key a fingerprint is usually given for */
+static bool key_has_fingerprint(gpgme_key_t key, const char *fpr)
+{
+    gpgme_subkey_t subkey;
+    for (subkey = key->subkeys; subkey; subkey = subkey->next) {
+        if (subkey->fpr && fingerprint_eq(subkey->fpr, fpr))
+            return true;
+    }
+
+    return false;
+}
+
 void gpgme_sign(int rootfd, const char *file, const char *key)
 {
     gpgme_error_t err;
     gpgme_ctx_t ctx;
     gpgme_data_t in, out;
     gpgme_sign_result_t result;
+    gpgme_key_t akey = NULL;
 
     if (init_gpgme() < 0)
-        return;
+        errx(EXIT_FAILURE, "signing requested, but gpg doesn't support OpenPGP");
 
     err = gpgme_new(&ctx);
     if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
         gpgme_err(EXIT_FAILURE, err, "failed to call gpgme_new()");
 
     if (key) {
-        gpgme_key_t akey;
-
         err = gpgme_get_key(ctx, key, &akey, 1);
         if (err)
             gpgme_err(EXIT_FAILURE, err, "failed to set key %s", key);
@@ -151,12 +269,8 @@ void gpgme_sign(int rootfd, const char *file, const char *key)
         err = gpgme_signers_add(ctx, akey);
         if (gpg_err_code(err) != GPG_ERR_NO_ERROR)
             gpgme_err(EXIT_FAILURE, err, "failed to call gpgme_signers_add()");
-
-        gpgme_key_unref(akey);
     }
 
-    _cleanup_free_ char *sigfile = sig_for(file);
-    _cleanup_close_ int sigfd = openat(rootfd, sigfile, O_CREAT | O_WRONLY, 00644);
     _cleanup_close_ int fd = openat(rootfd, file, O_RDONLY);
 
     err = gpgme_data_new_from_fd(&in, fd);
@@ -175,15 +289,28 @@ void gpgme_sign(int rootfd, const char *file, const char *key)
     if (!result)
         gpgme_err(EXIT_FAILURE, err, "signaure failed?");
 
+    /* The key was requested up front, but check which one gpg actually
+       used before publishing anything, in case it had other ideas */
+    if (akey) {
+        const char *fpr = result->signatures ? result->signatures->fpr : NULL;
+        if (!fpr || !key_has_fingerprint(akey, fpr))
+            errx(EXIT_FAILURE, "%s was signed by %s, expected a key of %s", file,
+                 fpr ? fpr : "an unknown key", key);
+
+        gpgme_key_unref(akey);
+    }
+
+    _cleanup_free_ char *sigfile = sig_for(file);
+    _cleanup_close_ int sigfd = openat(rootfd, sigfile, O_CREAT | O_WRONLY | O_TRUNC, 00644);
+    check_posix(sigfd, "failed to open %s", sigfile);
+
     char buf[BUFSIZ];
-    int ret;
+    ssize_t ret;
 
-    ret = gpgme_data_seek(out, 0, SEEK_SET);
-    if (ret)
-        return;
+    check_posix(gpgme_data_seek(out, 0, SEEK_SET), "failed to rewind signature for %s", file);
 
     while ((ret = gpgme_data_read(out, buf, BUFSIZ)) > 0)
-        write(sigfd, buf, ret);
+        check_posix(write(sigfd, buf, ret), "failed to write %s", sigfile);
 
     gpgme_data_release(out);
     gpgme_data_release(in);
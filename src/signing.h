@@ -1,7 +1,11 @@
 #ifndef SIGNING_H
 #define SIGNING_H
 
+#include <stddef.h>
+
+void gpgme_check_signing_key(const char *fingerprint);
 void gpgme_sign(int rootfd, const char *file, const char *key);
 int gpgme_verify(int rootfd, const char *file);
+int gpgme_verify_signature(int dirfd, const char *file, const char *signature, size_t len);
 
 #endif
@@ -3,6 +3,7 @@
 #include <stdlib.h>
 #include <stdarg.h>
 #include <string.h>
+#include <ctype.h>
 #include <time.h>
 #include <errno.h>
 #include <err.h>
@@ -150,11 +151,59 @@ int parse_size(const char *str, size_t *out)
     return 0;
 }
 
+/* Some tools write the build date as an RFC 3339 timestamp, such as
+   2015-11-28T06:04:29Z, rather than seconds since the epoch */
+static int parse_rfc3339(const char *str, time_t *out)
+{
+    struct tm tm = {0};
+    const char *p = strptime(str, "%Y-%m-%dT%H:%M:%S", &tm);
+    if (!p) {
+        errno = EINVAL;
+        return -1;
+    }
+
+    /* Fractional seconds, which the database has no room for anyway */
+    if (*p == '.' && isdigit((unsigned char)p[1])) {
+        p += 1;
+        p += strspn(p, "0123456789");
+    }
+
+    long offset = 0;
+    if (*p == 'Z' || *p == 'z') {
+        p += 1;
+    } else if (*p == '+' || *p == '-') {
+        int hours, minutes, len = 0;
+        if (sscanf(p + 1, "%2d:%2d%n", &hours, &minutes, &len) != 2 || len != 5) {
+            errno = EINVAL;
+            return -1;
+        }
+
+        offset = (hours * 60 + minutes) * 60;
+        if (*p == '-')
+            offset = -offset;
+        p += 1 + len;
+    }
+
+    if (*p) {
+        errno = EINVAL;
+        return -1;
+    }
+
+    time_t value = timegm(&tm) - offset;
+    if (value < 0 || value > INT_MAX) {
+        errno = ERANGE;
+        return -1;
+    }
+
+    *out = value;
+    return 0;
+}
+
 int parse_time(const char *str, time_t *out)
 {
     unsigned long value = 0;
     if (xstrtoul(str, &value) < 0)
-        return -1;
+        return errno == EINVAL ? parse_rfc3339(str, out) : -1;
 
     if (value > INT_MAX) {
         errno = ERANGE;
@@ -194,6 +243,38 @@ char *strstrip(char *s)
     return s;
 }
 
+/* A relative path that stays below wherever it gets extracted: not
+   absolute and without any ".." component */
+bool path_is_safe(const char *path)
+{
+    if (path[0] == '/')
+        return false;
+
+    while (*path) {
+        size_t len = strcspn(path, "/");
+        if (len == 2 && strneq(path, "..", 2))
+            return false;
+
+        path += len;
+        path += strspn(path, "/");
+    }
+
+    return true;
+}
+
+void timer_start(struct timespec *start)
+{
+    clock_gettime(CLOCK_MONOTONIC, start);
+}
+
+double timer_elapsed(const struct timespec *start)
+{
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+
+    return (now.tv_sec - start->tv_sec) + (now.tv_nsec - start->tv_nsec) / 1e9;
+}
+
 int archive_read(struct archive *archive, char **buf, size_t *buf_len)
 {
     for (;;) {
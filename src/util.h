@@ -52,6 +52,10 @@ int parse_size(const char *str, size_t *out);
 int parse_time(const char *str, time_t *out);
 
 char *strstrip(char *s);
+bool path_is_safe(const char *path);
 char *hex_representation(unsigned char *bytes, size_t size);
 
 int archive_read(struct archive *archive, char **buf, size_t *buf_len);
+
+void timer_start(struct timespec *start);
+double timer_elapsed(const struct timespec *start);
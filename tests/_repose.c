@@ -1,6 +1,8 @@
 #define SIZE_MAX ...
+#define LINE_MAX ...
 
 typedef int... time_t;
+typedef uint64_t hash_t;
 
 typedef struct __alpm_list_t {
     void *data;
@@ -9,6 +11,7 @@ typedef struct __alpm_list_t {
 } alpm_list_t;
 
 struct pkg {
+    hash_t hash;
     char *filename;
     char *name;
     char *base;
@@ -19,6 +22,7 @@ struct pkg {
     char *sha256sum;
     char *base64sig;
     char *arch;
+    char *pkgtype;
     size_t size;
     size_t isize;
     time_t builddate;
@@ -34,6 +38,15 @@ struct pkg {
     alpm_list_t *makedepends;
     alpm_list_t *checkdepends;
     alpm_list_t *files;
+    alpm_list_t *deltas;
+    alpm_list_t *makepkgopts;
+    alpm_list_t *validations;
+    alpm_list_t *backups;
+    alpm_list_t *dropped;
+    alpm_list_t *unknown_desc;
+    alpm_list_t *unknown_depends;
+    alpm_list_t *unknown_files;
+    alpm_list_t *entries;
     ...;
 };
 
@@ -63,12 +76,50 @@ enum pkg_entry {
     PKG_FILES,
     PKG_BACKUP,
     PKG_DELTAS,
-    PKG_MAKEPKGOPT
+    PKG_MAKEPKGOPT,
+    PKG_BUILDDIR,
+    PKG_BUILDENV,
+    PKG_INSTALLED,
+    PKG_PKGBUILD_SHA256SUM,
+    PKG_VALIDATION,
+    PKG_XDATA,
+    PKG_UNKNOWN_DESC,
+    PKG_UNKNOWN_DEPENDS,
+    PKG_UNKNOWN_FILES
 };
 
+int package_build_option(const struct pkg *pkg, const char *option);
+bool package_arch_is_any(const struct pkg *pkg);
+bool package_is_debug(const struct pkg *pkg);
+
+enum pkg_invalid {
+    PKG_INVALID_NAME    = 1,
+    PKG_INVALID_VERSION = 2
+};
+
+int package_validate(const struct pkg *pkg);
+bool package_satisfies(const struct pkg *pkg, const char *depend);
+bool package_entry_matches(const struct pkg *pkg, const char *entry);
+bool package_depends_on(const struct pkg *pkg, enum pkg_entry kind, const char *name);
+bool package_requires(const struct pkg *pkg, enum pkg_entry kind, const struct pkg *target);
+
+// pkgcache
+struct pkgcache {
+    alpm_list_t *list;
+    ...;
+};
+
+hash_t sdbm(const char *str);
+struct pkgcache *pkgcache_create(size_t size);
+void pkgcache_free(struct pkgcache *cache);
+struct pkgcache *pkgcache_add(struct pkgcache *cache, struct pkg *pkg);
+alpm_list_t *pkgcache_find_provides(struct pkgcache *cache, const char *depend);
+void alpm_list_free(alpm_list_t *list);
+
 // desc
 struct desc_parser {
     enum pkg_entry entry;
+    enum pkg_entry unknown;
     ...;
 };
 
@@ -86,8 +137,24 @@ void pkginfo_parser_init(struct pkginfo_parser *parser);
 ssize_t pkginfo_parser_feed(struct pkginfo_parser *parser, struct pkg *pkg,
                             char *buf, size_t buf_len);
 
+void write_pkginfo(struct buffer *buf, const struct pkg *pkg);
+
+void write_desc(struct buffer *buf, const struct pkg *pkg);
+void write_depends(struct buffer *buf, const struct pkg *pkg);
+void write_files(struct buffer *buf, const struct pkg *pkg);
+
+// buffer
+struct buffer {
+    char *data;
+    size_t len;
+    ...;
+};
+
+void buffer_release(struct buffer *buf);
+
 // utils
 char *joinstring(const char *root, ...);
 int parse_size(const char *str, size_t *out);
 int parse_time(const char *size, time_t *out);
 char *strstrip(char *s);
+bool path_is_safe(const char *path);
@@ -1,5 +1,7 @@
 #include <time.h>
 #include <repose.h>
 #include <desc.h>
+#include <pkgcache.h>
 #include <pkginfo.h>
 #include <util.h>
+#include <buffer.h>
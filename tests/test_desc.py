@@ -1,3 +1,4 @@
+import os
 import pytest
 from datetime import datetime
 from repose import lib, ffi
@@ -106,6 +107,20 @@ def test_parse_depends(pkg, parser):
     assert pkg.makedepends == ['git']
 
 
+def test_parse_deltas(pkg, parser):
+    parser.feed(pkg, '''%FILES%
+usr/bin/repose
+
+%DELTAS%
+repose-6.2-1_to_6.2-2-x86_64.delta 0a3c4b5d 1024 6.2-1 6.2-2
+
+''')
+    assert parser.entry == lib.PKG_DELTAS
+
+    assert pkg.files == ['usr/bin/repose']
+    assert pkg.deltas == ['repose-6.2-1_to_6.2-2-x86_64.delta 0a3c4b5d 1024 6.2-1 6.2-2']
+
+
 @pytest.mark.parametrize('chunksize', [1, 10, 100])
 def test_parse_chunked(pkg, parser, chunksize):
     def chunk(data, size):
@@ -126,3 +141,178 @@ def test_parse_chunked(pkg, parser, chunksize):
     assert pkg.builddate == "Nov 28, 2015, 06:04:29"
     assert pkg.packager == 'Simon Gomizelj <simongmzlj@gmail.com>'
     assert pkg.licenses == ['GPL']
+
+
+REPOSE_FILES = '''%FILES%
+usr/
+usr/bin/
+usr/bin/repose
+'''
+
+
+def test_parse_split_entries(pkg):
+    # Older databases keep desc, depends and files as separate members
+    # of the package's directory. Each is parsed into the same package.
+    for member in (REPOSE_DESC, REPOSE_DEPENDS, REPOSE_FILES):
+        DescParser().feed(pkg, member)
+
+    assert pkg.filename == 'repose-git-5.19.g82c3d4a-1-x86_64.pkg.tar.xz'
+    assert pkg.depends == ['pacman', 'libarchive', 'gnupg']
+    assert pkg.makedepends == ['git']
+    assert pkg.files == ['usr/', 'usr/bin/', 'usr/bin/repose']
+
+
+# Byte for byte what repose writes for the package above: every section
+# is closed by a blank line, including the last, and %BASE% falls back to
+# the package name.
+GOLDEN_DESC = '''%FILENAME%
+repose-git-5.19.g82c3d4a-1-x86_64.pkg.tar.xz
+
+%NAME%
+repose-git
+
+%BASE%
+repose-git
+
+%VERSION%
+5.19.g82c3d4a-1
+
+%DESC%
+A archlinux repo building tool
+
+%CSIZE%
+18804
+
+%ISIZE%
+51200
+
+%SHA256SUM%
+4045b3b24bae8a2d811323e5dd3727345e9e6f81788c65d5935d07b2ee06b505
+
+%URL%
+http://github.com/vodik/repose
+
+%LICENSE%
+GPL
+
+%ARCH%
+x86_64
+
+%BUILDDATE%
+1448690669
+
+%PACKAGER%
+Simon Gomizelj <simongmzlj@gmail.com>
+
+'''
+
+
+def write(writer, pkg):
+    buf = ffi.new('struct buffer*')
+    writer(buf, pkg._struct)
+    output = ffi.string(buf.data, buf.len).decode()
+    lib.buffer_release(buf)
+    return output
+
+
+def test_write_desc(pkg, parser):
+    parser.feed(pkg, REPOSE_DESC)
+    assert write(lib.write_desc, pkg) == GOLDEN_DESC
+
+
+def test_write_depends(pkg, parser):
+    parser.feed(pkg, REPOSE_DEPENDS)
+    assert write(lib.write_depends, pkg) == REPOSE_DEPENDS + '\n'
+
+
+def test_write_desc_newlines():
+    pkg = Package('desc-newlines', '1.0-1')
+    desc = ffi.new('char[]', b'A description\nspread over\nthree lines')
+    pkg._struct.desc = desc
+
+    output = write(lib.write_desc, pkg)
+    assert '%DESC%\nA description spread over three lines\n\n' in output
+
+
+def test_unknown_fields_roundtrip(pkg, parser):
+    future = '''%FUTURE%
+something new
+
+%FUTURE_LIST%
+one
+two
+
+'''
+    parser.feed(pkg, REPOSE_DESC + '\n' + future)
+    assert parser.entry == lib.PKG_UNKNOWN_DESC
+
+    assert pkg.name == 'repose-git'
+    assert pkg.unknown_desc == ['%FUTURE%', 'something new',
+                                '%FUTURE_LIST%', 'one', 'two']
+    assert write(lib.write_desc, pkg) == GOLDEN_DESC + future
+
+
+def test_unknown_fields_read_twice(pkg, parser):
+    # repo-add's files database repeats desc, so the same member is
+    # parsed again, by a new parser, into the package already loaded
+    future = '''%FUTURE%
+something new
+
+'''
+    parser.feed(pkg, REPOSE_DESC + '\n' + future)
+    DescParser().feed(pkg, REPOSE_DESC + '\n' + future)
+
+    assert pkg.unknown_desc == ['%FUTURE%', 'something new']
+    assert write(lib.write_desc, pkg).count('%FUTURE%') == 1
+
+
+def test_unknown_fields_stay_in_their_member(pkg, parser):
+    future_depends = '''%FUTURE_DEPENDS%
+something
+
+'''
+    parser.parser.unknown = lib.PKG_UNKNOWN_DEPENDS
+    parser.feed(pkg, REPOSE_DEPENDS + '\n' + future_depends)
+    assert parser.entry == lib.PKG_UNKNOWN_DEPENDS
+
+    files = '''%FILES%
+usr/bin/repose
+
+%FUTURE_FILES%
+else
+
+'''
+    files_parser = DescParser()
+    files_parser.parser.unknown = lib.PKG_UNKNOWN_FILES
+    files_parser.feed(pkg, files)
+
+    assert pkg.unknown_desc == []
+    assert pkg.unknown_depends == ['%FUTURE_DEPENDS%', 'something']
+    assert pkg.unknown_files == ['%FUTURE_FILES%', 'else']
+
+    assert 'FUTURE' not in write(lib.write_desc, pkg)
+    assert write(lib.write_depends, pkg) == REPOSE_DEPENDS + '\n' + future_depends
+    assert write(lib.write_files, pkg) == files
+
+
+@pytest.mark.parametrize('entry,matches', [
+    ('repose-git-5.19.g82c3d4a-1', True),
+    ('repose-git-5.18.g1a2b3c4-1', False),
+    ('repose-5.19.g82c3d4a-1', False),
+    ('repose-git5.19.g82c3d4a-1', False),
+])
+def test_entry_matches(pkg, parser, entry, matches):
+    parser.feed(pkg, REPOSE_DESC)
+    assert lib.package_entry_matches(pkg._struct, entry.encode()) == matches
+
+
+def test_mismatched_entry_fixture(pkg, parser):
+    # A database entry left behind under the previous version's directory
+    entry = os.path.join(os.path.dirname(__file__), 'fixtures',
+                         'repose-git-5.18.g1a2b3c4-1')
+    with open(os.path.join(entry, 'desc')) as desc:
+        parser.feed(pkg, desc.read())
+
+    assert pkg.version == '5.19.g82c3d4a-1'
+    assert not lib.package_entry_matches(pkg._struct,
+                                         os.path.basename(entry).encode())
@@ -1,3 +1,5 @@
+import os
+import glob
 import pytest
 from datetime import datetime
 from repose import lib, ffi
@@ -80,6 +82,90 @@ def test_parse_chunked(pkg, parser, chunksize):
     assert pkg.licenses == ['GPL']
 
 
+@pytest.mark.parametrize('chunksize', [1, 2, 10, 100])
+def test_parse_bom(pkg, parser, chunksize):
+    # Chunk the encoded bytes, so the BOM really gets split across feeds
+    data = b'\xef\xbb\xbf' + REPOSE_PKGINFO.encode()
+    for i in range(0, len(data), chunksize):
+        chunk = data[i:i+chunksize]
+        assert parser.feed_parser(parser.parser, pkg._struct, chunk) == len(chunk)
+
+    assert pkg.desc == 'A archlinux repo building tool'
+    assert pkg.arch == 'x86_64'
+
+
+def test_parse_partial_bom(pkg, parser):
+    data = b'\xef\xbb' + REPOSE_PKGINFO.encode()
+    assert parser.feed_parser(parser.parser, pkg._struct, data) == -1
+
+
+def test_parse_reordered(pkg, parser):
+    # Move the name and version to the end, keeping the other entries,
+    # and so the order within lists, as they were
+    lines = REPOSE_PKGINFO.splitlines(keepends=True)
+    ident = [line for line in lines if line.startswith(('pkgname', 'pkgver'))]
+    rest = [line for line in lines if line not in ident]
+    parser.feed(pkg, ''.join(rest + ident))
+
+    expected = Package()
+    PKGINFOParser().feed(expected, REPOSE_PKGINFO)
+
+    assert pkg.name == 'repose-git'
+    assert pkg.version == '6.2.10.gbab93f3-1'
+    assert pkg.arch == 'x86_64'
+    assert pkg == expected
+
+
+def test_parse_arch_first(pkg, parser):
+    parser.feed(pkg, '''arch = any
+pkgdesc = Metadata before the name
+pkgver = 1.0-1
+pkgname = arch_first
+''')
+
+    assert pkg.name == 'arch_first'
+    assert pkg.version == '1.0-1'
+    assert pkg.desc == 'Metadata before the name'
+    assert lib.package_arch_is_any(pkg._struct)
+
+
+def test_pkginfo_blank_lines(pkg, parser):
+    parser.feed(pkg, '''
+# Generated by something other than makepkg
+
+pkgname = pkginfo_blank_lines
+\t
+pkgver = 1.0-1
+
+''')
+
+    assert pkg.name == 'pkginfo_blank_lines'
+    assert pkg.version == '1.0-1'
+
+
+@pytest.mark.parametrize('chunksize', [1, 4096])
+def test_pkginfo_longest_line(pkg, parser, chunksize):
+    # The parser is a state machine that looks at each byte once, so the
+    # only limit on a value is the line buffer it's collected into
+    value = 'x' * (lib.LINE_MAX - 1)
+    data = '''pkgname = pkginfo_longest_line
+depend = {}
+'''.format(value)
+
+    for i in range(0, len(data), chunksize):
+        parser.feed(pkg, data[i:i+chunksize])
+
+    assert pkg.depends == [value]
+
+
+def test_pkginfo_line_too_long(pkg, parser):
+    value = 'x' * 0x10000
+    with pytest.raises(ParserError):
+        parser.feed(pkg, '''pkgname = pkginfo_line_too_long
+depend = {}
+'''.format(value))
+
+
 def test_pkginfo_with_backup(pkg, parser):
     parser.feed(pkg, '''pkgname = example
 backup = etc/example/conf
@@ -99,8 +185,340 @@ url =
 ''')
 
 
+def test_empty_pkginfo_list_entry(pkg, parser):
+    parser.feed(pkg, '''pkgname = empty_pkginfo_list_entry
+depend =
+depend = 
+depend = pacman
+''')
+
+    assert pkg.depends == ['pacman']
+
+
+def test_pkginfo_backslashes(pkg, parser):
+    parser.feed(pkg, r'''pkgname = pkginfo_backslashes
+builddir = C:\build\foo
+pkgdesc = Built in C:\build\foo\ with \\ and \n left alone
+''')
+
+    assert pkg.desc == r'Built in C:\build\foo\ with \\ and \n left alone'
+    assert pkg.dropped == ['builddir']
+
+
+def test_validation(pkg, parser):
+    parser.feed(pkg, '''pkgname = validation
+validation = sha256
+validation = pgp
+''')
+
+    assert parser.entry == lib.PKG_VALIDATION
+    assert pkg.validations == ['sha256', 'pgp']
+
+
+def test_pkginfo_tabs(pkg, parser):
+    parser.feed(pkg, '''pkgname\t=\tpkginfo_tabs
+pkgdesc\t= \tA package built by something other than makepkg
+depend =\tpacman
+depend\t\t=\t
+''')
+
+    assert pkg.name == 'pkginfo_tabs'
+    assert pkg.desc == 'A package built by something other than makepkg'
+    assert pkg.depends == ['pacman']
+
+
+def test_pkginfo_trailing_whitespace(pkg, parser):
+    parser.feed(pkg, '''pkgname = pkginfo_trailing_whitespace \t
+pkgdesc = A description  with  its  spacing intact  
+depend = pacman\t
+depend = glibc>=2.26 
+''')
+
+    assert pkg.name == 'pkginfo_trailing_whitespace'
+    assert pkg.desc == 'A description  with  its  spacing intact'
+    assert pkg.depends == ['pacman', 'glibc>=2.26']
+
+
+def test_pkginfo_groups(pkg, parser):
+    parser.feed(pkg, '''pkgname = pkginfo_groups
+group = base
+groups = base-devel
+''')
+
+    assert parser.entry == lib.PKG_GROUPS
+    assert pkg.groups == ['base', 'base-devel']
+
+
+@pytest.mark.parametrize('key,field', [
+    ('conflict', 'conflicts'),
+    ('conflicts', 'conflicts'),
+    ('provide', 'provides'),
+    ('provides', 'provides'),
+    ('replace', 'replaces'),
+    ('replaces', 'replaces'),
+    ('license', 'licenses'),
+    ('licenses', 'licenses'),
+])
+def test_pkginfo_plurals(pkg, parser, key, field):
+    parser.feed(pkg, '''pkgname = pkginfo_plurals
+{0} = first
+{0} = second
+'''.format(key))
+
+    assert getattr(pkg, field) == ['first', 'second']
+
+
 def test_makepkgopt(pkg, parser):
     parser.feed(pkg, '''pkgname = ttf-ms-win10-sea
 makepkgopt = strip
 makepkgopt = !debug
 ''')
+
+    assert pkg.makepkgopts == ['strip', '!debug']
+    assert lib.package_build_option(pkg._struct, b'strip') == 1
+    assert lib.package_build_option(pkg._struct, b'debug') == 0
+    assert lib.package_build_option(pkg._struct, b'ccache') == -1
+
+
+def test_dropped_pkginfo_entries(pkg, parser):
+    parser.feed(pkg, '''pkgname = dropped_pkginfo_entries
+builddir = /build/dropped
+buildenv = ccache
+buildenv = !distcc
+pkgbuild_sha256sum = 4045b3b24bae8a2d811323e5dd3727345e9e6f81788c65d5935d07b2ee06b505
+''')
+
+    assert pkg.dropped == ['builddir', 'buildenv', 'pkgbuild_sha256sum']
+
+
+@pytest.mark.parametrize('arch,is_any', [
+    ('any', True),
+    ('x86_64', False),
+])
+def test_arch_is_any(pkg, parser, arch, is_any):
+    parser.feed(pkg, '''pkgname = arch_is_any
+arch = {}
+'''.format(arch))
+
+    assert pkg.arch == arch
+    assert lib.package_arch_is_any(pkg._struct) == is_any
+
+
+@pytest.mark.parametrize('name,is_debug', [
+    ('repose-debug', True),
+    ('repose', False),
+    ('debug', False),
+    ('-debug', False),
+])
+def test_is_debug(pkg, parser, name, is_debug):
+    parser.feed(pkg, '''pkgname = {}
+'''.format(name))
+
+    assert lib.package_is_debug(pkg._struct) == is_debug
+
+
+@pytest.mark.parametrize('name,pkgtype,is_debug', [
+    ('repose-debug', 'debug', True),
+    ('repose', 'pkg', False),
+    ('perl-devel-debug', 'pkg', False),
+])
+def test_pkgtype(pkg, parser, name, pkgtype, is_debug):
+    parser.feed(pkg, '''pkgname = {}
+pkgbase = repose
+xdata = pkgtype={}
+'''.format(name, pkgtype))
+
+    assert parser.entry == lib.PKG_XDATA
+    assert pkg.pkgtype == pkgtype
+    assert lib.package_is_debug(pkg._struct) == is_debug
+    assert pkg.dropped == []
+
+
+@pytest.mark.parametrize('name,version,invalid', [
+    ('repose-git', '6.2.10.gbab93f3-1', 0),
+    ('lib32-gcc-libs', '1:6.2.1-1.1', 0),
+    ('libc++', '3.9.0-1', 0),
+    ('Repose', '1.0-1', 0),
+    ('repose@2', '1.0-1', 0),
+    ('-repose', '1.0-1', lib.PKG_INVALID_NAME),
+    ('.repose', '1.0-1', lib.PKG_INVALID_NAME),
+    ('repose git', '1.0-1', lib.PKG_INVALID_NAME),
+    ('repose/git', '1.0-1', lib.PKG_INVALID_NAME),
+    ('repose', '1.0', lib.PKG_INVALID_VERSION),
+    ('repose', '-1', lib.PKG_INVALID_VERSION),
+    ('repose', '1.0-', lib.PKG_INVALID_VERSION),
+    ('repose', '1.0-1.', lib.PKG_INVALID_VERSION),
+    ('repose', 'a:1.0-1', lib.PKG_INVALID_VERSION),
+    ('repose', '1:-1', lib.PKG_INVALID_VERSION),
+    ('repose', '1.0 rc1-1', lib.PKG_INVALID_VERSION),
+    ('', '', lib.PKG_INVALID_NAME | lib.PKG_INVALID_VERSION),
+])
+def test_validate(name, version, invalid):
+    pkg = Package(name=name, version=version)
+    assert lib.package_validate(pkg._struct) == invalid
+
+
+def test_write_pkginfo(pkg, parser):
+    parser.feed(pkg, REPOSE_PKGINFO)
+
+    buf = ffi.new('struct buffer*')
+    lib.write_pkginfo(buf, pkg._struct)
+    output = ffi.string(buf.data, buf.len).decode()
+    lib.buffer_release(buf)
+
+    # Everything but the generator comments comes back out verbatim
+    expected = ''.join(line + '\n' for line in REPOSE_PKGINFO.splitlines()
+                       if not line.startswith('#'))
+    assert output == expected
+
+    reparsed = Package()
+    PKGINFOParser().feed(reparsed, output)
+    assert reparsed == pkg
+
+
+def test_write_pkginfo_backups(pkg, parser):
+    parser.feed(pkg, REPOSE_PKGINFO + 'backup = etc/repose.conf\n')
+    assert pkg.backups == ['etc/repose.conf']
+
+    buf = ffi.new('struct buffer*')
+    lib.write_pkginfo(buf, pkg._struct)
+    output = ffi.string(buf.data, buf.len).decode()
+    lib.buffer_release(buf)
+
+    assert 'provides = repose\nbackup = etc/repose.conf\ndepend = pacman\n' in output
+
+
+def test_write_pkginfo_newlines():
+    pkg = Package('desc-newlines', '1.0-1')
+    desc = ffi.new('char[]', b'A description\nspread over\nthree lines')
+    pkg._struct.desc = desc
+
+    buf = ffi.new('struct buffer*')
+    lib.write_pkginfo(buf, pkg._struct)
+    output = ffi.string(buf.data, buf.len).decode()
+    lib.buffer_release(buf)
+
+    assert 'pkgdesc = A description spread over three lines\n' in output
+
+
+FIXTURES = sorted(glob.glob(os.path.join(os.path.dirname(__file__),
+                                         'fixtures', '*.PKGINFO')))
+
+
+@pytest.mark.parametrize('path', FIXTURES, ids=os.path.basename)
+def test_pkginfo_roundtrip(path):
+    with open(path) as fixture:
+        pkginfo = fixture.read()
+
+    pkg = Package()
+    PKGINFOParser().feed(pkg, pkginfo)
+    assert pkg.name
+
+    buf = ffi.new('struct buffer*')
+    lib.write_pkginfo(buf, pkg._struct)
+    output = ffi.string(buf.data, buf.len).decode()
+    lib.buffer_release(buf)
+
+    reparsed = Package()
+    PKGINFOParser().feed(reparsed, output)
+
+    # Fields we drop on purpose never make it back out
+    mismatched = [field for field in Package.fields()
+                  if field != 'dropped' and getattr(pkg, field) != getattr(reparsed, field)]
+    assert mismatched == []
+
+
+@pytest.mark.parametrize('depend,satisfied', [
+    ('repose-git', True),
+    ('repose-git=6.2.10.gbab93f3-1', True),
+    ('repose-git=6.2.10.gbab93f3', True),
+    ('repose-git>=6', True),
+    ('repose-git<6', False),
+    ('repose-git>6.2.10.gbab93f3-1', False),
+    ('repose', True),
+    ('repose>=1', False),
+    ('repose-gi', False),
+    ('librepose.so', True),
+    ('librepose.so=1-64', True),
+    ('librepose.so=2-64', False),
+    ('repose-ng>=2', True),
+    ('repose-ng<2', False),
+])
+def test_satisfies(pkg, parser, depend, satisfied):
+    parser.feed(pkg, '''pkgname = repose-git
+pkgver = 6.2.10.gbab93f3-1
+provides = repose
+provides = librepose.so=1-64
+provides = repose-ng=2.0-1
+''')
+
+    assert lib.package_satisfies(pkg._struct, depend.encode()) == satisfied
+
+
+@pytest.mark.parametrize('depend,providers', [
+    ('repose', ['repose', 'repose-git']),
+    ('repose>=6', ['repose']),
+    ('repose-git', ['repose-git']),
+    ('librepose.so', ['repose-git']),
+    ('librepose.so=2-64', []),
+    ('pacman', ['pacman']),
+    ('pacman>=6', []),
+])
+def test_find_provides(depend, providers):
+    pkginfos = [
+        'pkgname = repose\npkgver = 6.2-1\n',
+        'pkgname = repose-git\npkgver = 6.2.10.gbab93f3-1\n'
+        'provides = repose\nprovides = librepose.so=1-64\n',
+        'pkgname = pacman\npkgver = 5.0.1-1\n',
+    ]
+
+    packages = []
+    cache = lib.pkgcache_create(len(pkginfos))
+    for pkginfo in pkginfos:
+        pkg = Package()
+        PKGINFOParser().feed(pkg, pkginfo)
+        pkg._struct.hash = lib.sdbm(pkg._struct.name)
+        cache = lib.pkgcache_add(cache, pkg._struct)
+        packages.append(pkg)
+
+    found = lib.pkgcache_find_provides(cache, depend.encode())
+    names = []
+    node = found
+    while node:
+        names.append(ffi.string(ffi.cast('struct pkg*', node.data).name).decode())
+        node = node.next
+
+    lib.alpm_list_free(found)
+    lib.pkgcache_free(cache)
+    assert sorted(names) == providers
+
+
+@pytest.mark.parametrize('kind,name,depends', [
+    (lib.PKG_DEPENDS, 'pacman', True),
+    (lib.PKG_DEPENDS, 'libarchive', True),
+    (lib.PKG_DEPENDS, 'libarchiv', False),
+    (lib.PKG_DEPENDS, 'git', False),
+    (lib.PKG_MAKEDEPENDS, 'git', True),
+    (lib.PKG_CHECKDEPENDS, 'git', False),
+])
+def test_depends_on(pkg, parser, kind, name, depends):
+    parser.feed(pkg, '''pkgname = repose-git
+depend = pacman>=5
+depend = libarchive
+makedepend = git
+''')
+
+    assert lib.package_depends_on(pkg._struct, kind, name.encode()) == depends
+
+
+@pytest.mark.parametrize('version,required', [
+    ('5.0.1-1', True),
+    ('4.2.1-1', False),
+])
+def test_requires(pkg, parser, version, required):
+    parser.feed(pkg, '''pkgname = repose-git
+depend = pacman>=5
+''')
+
+    target = Package('pacman', version)
+    assert lib.package_requires(pkg._struct, lib.PKG_DEPENDS, target._struct) == required
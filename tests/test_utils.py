@@ -28,6 +28,22 @@ def test_strstrip(input):
     assert ffi.string(result) == input.strip()
 
 
+@pytest.mark.parametrize('path,safe', [
+    (b'usr/bin/repose', True),
+    (b'usr/share/doc/', True),
+    (b'usr/lib/..foo', True),
+    (b'.PKGINFO', True),
+    (b'/etc/passwd', False),
+    (b'../../etc/passwd', False),
+    (b'usr/../../etc/passwd', False),
+    (b'usr//..', False),
+])
+def test_path_is_safe(path, safe):
+    arg = ffi.new('char[]', path)
+
+    assert lib.path_is_safe(arg) == safe
+
+
 def test_parse_size():
     arg = ffi.new('char[]', b'832421')
     out = ffi.new('size_t *')
@@ -52,3 +68,30 @@ def test_parse_time():
 
     assert lib.parse_time(arg, out) == 0
     assert out[0] == 1448690669
+
+
+@pytest.mark.parametrize('input', [
+    b'2015-11-28T06:04:29Z',
+    b'2015-11-28T06:04:29.250Z',
+    b'2015-11-28T07:04:29+01:00',
+    b'2015-11-27T23:04:29-07:00',
+])
+def test_parse_time_rfc3339(input):
+    arg = ffi.new('char[]', input)
+    out = ffi.new('time_t *')
+
+    assert lib.parse_time(arg, out) == 0
+    assert out[0] == 1448690669
+
+
+@pytest.mark.parametrize('input', [
+    b'2015-11-28',
+    b'2015-11-28T06:04:29+0100',
+    b'2015-11-28T06:04:29Z trailing',
+])
+def test_parse_time_EINVAL(input):
+    arg = ffi.new('char[]', input)
+    out = ffi.new('time_t *')
+
+    assert lib.parse_time(arg, out) == -1
+    assert ffi.errno == errno.EINVAL
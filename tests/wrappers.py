@@ -47,6 +47,16 @@ class marshal_string_list(object):
 
 
 class Package(object):
+    @classmethod
+    def fields(cls):
+        return sorted(name for name, value in vars(cls).items()
+                      if isinstance(value, (marshal_int, marshal_date, marshal_string,
+                                            marshal_string_list)))
+
+    def __eq__(self, other):
+        return all(getattr(self, field) == getattr(other, field)
+                   for field in self.fields())
+
     def __init__(self, name=None, version=None):
         self.weakkeydict = weakref.WeakKeyDictionary()
 
@@ -60,23 +70,37 @@ class Package(object):
         self.weakkeydict[self._struct] = tuple(init_data.values())
 
     arch = marshal_string('arch')
+    backups = marshal_string_list('backups')
     base = marshal_string('base')
     base64sig = marshal_string('base64sig')
     builddate = marshal_date('builddate')
     checkdepends = marshal_string_list('checkdepends')
     conflicts = marshal_string_list('conflicts')
+    deltas = marshal_string_list('deltas')
     depends = marshal_string_list('depends')
     desc = marshal_string('desc')
+    dropped = marshal_string_list('dropped')
     filename = marshal_string('filename')
+    files = marshal_string_list('files')
+    groups = marshal_string_list('groups')
     isize = marshal_int('isize')
     licenses = marshal_string_list('licenses')
     makedepends = marshal_string_list('makedepends')
+    makepkgopts = marshal_string_list('makepkgopts')
+    name = marshal_string('name')
     optdepends = marshal_string_list('optdepends')
     packager = marshal_string('packager')
+    pkgtype = marshal_string('pkgtype')
     provides = marshal_string_list('provides')
+    replaces = marshal_string_list('replaces')
     sha256sum = marshal_string('sha256sum')
+    unknown_depends = marshal_string_list('unknown_depends')
+    unknown_desc = marshal_string_list('unknown_desc')
+    unknown_files = marshal_string_list('unknown_files')
     size = marshal_int('size')
     url = marshal_string('url')
+    validations = marshal_string_list('validations')
+    version = marshal_string('version')
 
 
 class ParserError(Exception):